@@ -0,0 +1,68 @@
+//! Related songs, played once the queue runs out.
+
+use std::collections::VecDeque;
+
+use anyhow::Context as _;
+use serenity::client::Context;
+use songbird::{input::restartable::Restartable, tracks::TrackHandle};
+
+use crate::{
+    resolver, track,
+    youtube::{self, SearchResult},
+};
+
+/// Number of recently played songs which autoplay doesn't pick again.
+const AUTOPLAY_HISTORY: usize = 50;
+
+/// Number of search results autoplay picks a related song from.
+const AUTOPLAY_CANDIDATES: usize = 10;
+
+/// Songs played recently, which autoplay doesn't pick again.
+#[derive(Default, Clone)]
+pub struct History {
+    /// URLs and lowercase titles, the most recent last.
+    songs: VecDeque<(Option<String>, String)>,
+}
+
+impl History {
+    pub fn push(&mut self, handle: &TrackHandle) {
+        let url = handle.metadata().source_url.clone();
+        self.songs
+            .push_back((url, track::title_of(handle).to_lowercase()));
+        while self.songs.len() > AUTOPLAY_HISTORY {
+            self.songs.pop_front();
+        }
+    }
+
+    fn contains(&self, result: &SearchResult) -> bool {
+        let url = youtube::video_url(&result.id);
+        let title = result.title.to_lowercase();
+        self.songs.iter().any(|(played_url, played_title)| {
+            played_url.as_deref() == Some(url.as_str()) || *played_title == title
+        })
+    }
+}
+
+/// Finds a song related to `last` which isn't in `history`, searching for its
+/// artist or, when unknown, for its title. Returns the source and URL of the
+/// song.
+pub async fn related(
+    ctx: &Context,
+    history: &History,
+    last: &TrackHandle,
+) -> anyhow::Result<(Restartable, String)> {
+    let terms = last
+        .metadata()
+        .artist
+        .clone()
+        .filter(|artist| !artist.is_empty())
+        .unwrap_or_else(|| track::title_of(last));
+    let result = youtube::search(&terms, AUTOPLAY_CANDIDATES)
+        .await?
+        .into_iter()
+        .find(|result| !history.contains(result))
+        .with_context(|| format!("No new song found for {}", terms))?;
+    let url = youtube::video_url(&result.id);
+    let source = resolver::get(ctx).await.resolve_one(&url).await?;
+    Ok((source, url))
+}
@@ -0,0 +1,958 @@
+//! Implementations of the commands, shared by the prefix and slash commands.
+//!
+//! They call the [`MusicController`] and turn its results into messages.
+
+use std::{collections::VecDeque, time::Duration};
+
+use futures::StreamExt;
+use serenity::{
+    async_trait,
+    client::Context,
+    framework::standard::CommandResult,
+    model::{
+        channel::{Message, ReactionType},
+        id::{ChannelId, GuildId, UserId},
+    },
+    prelude::{Mentionable, Mutex},
+};
+
+use crate::{
+    check_msg,
+    controller::{MusicController, MusicResult, SkipVote},
+    dj, idle,
+    invocation::Invocation,
+    resolver::{self, Progress, Song},
+    settings, track, youtube, LoopMode,
+};
+
+/// Longest search terms shown in the title of the results, which Discord
+/// limits to 256 characters.
+const SEARCH_TITLE_TERMS: usize = 200;
+
+/// How long the author of `~search` can choose one of the results.
+const SEARCH_CHOICE_SECS: u64 = 60;
+
+/// Reactions choosing the results of `~search`, which shows as many results.
+const SEARCH_CHOICES: [&str; 5] = ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣"];
+
+/// How long the author of a link to a video within a playlist can choose what
+/// to play, before just the video is played.
+const PLAYLIST_CHOICE_SECS: u64 = 30;
+
+const PLAYLIST_CHOICE_VIDEO: &str = "🎵";
+const PLAYLIST_CHOICE_PLAYLIST: &str = "📜";
+
+/// Number of songs loaded in the background between two updates of the
+/// progress of an import.
+const IMPORT_PROGRESS_INTERVAL: usize = 50;
+
+/// Number of songs which failed to load listed after an import.
+const IMPORT_FAILURES_SHOWN: usize = 5;
+
+/// Width of the progress bar shown by `~nowplaying`.
+const NOW_PLAYING_BAR_WIDTH: usize = 20;
+
+/// Number of upcoming tracks shown by `~nowplaying`.
+const NOW_PLAYING_UP_NEXT: usize = 3;
+
+/// Number of tracks on each page of `~list`.
+const LIST_PAGE_SIZE: usize = 10;
+
+/// How long the pages of `~list` can be navigated with reactions.
+const LIST_NAVIGATION_SECS: u64 = 120;
+
+const LIST_PREVIOUS: &str = "◀️";
+const LIST_NEXT: &str = "▶️";
+
+/// Sends the message describing the result of a command, or its error.
+async fn report(ctx: &Context, inv: &Invocation, result: MusicResult<String>) {
+    let content = result.unwrap_or_else(|why| why.to_string());
+    check_msg(inv.say(ctx, content).await);
+}
+
+/// The voice channel the author is in.
+async fn voice_channel(ctx: &Context, inv: &Invocation) -> Option<ChannelId> {
+    inv.guild_id
+        .to_guild_cached(&ctx.cache)
+        .await?
+        .voice_states
+        .get(&inv.author)?
+        .channel_id
+}
+
+pub async fn deafen(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).deafen(inv.guild_id, true).await;
+    report(
+        ctx,
+        inv,
+        result.map(|deafened| {
+            if deafened {
+                "Deafened"
+            } else {
+                "Already deafened"
+            }
+            .to_string()
+        }),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn undeafen(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).deafen(inv.guild_id, false).await;
+    report(ctx, inv, result.map(|_| "Undeafened".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn mute(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).mute(inv.guild_id, true).await;
+    report(
+        ctx,
+        inv,
+        result.map(|muted| if muted { "Now muted" } else { "Already muted" }.to_string()),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn unmute(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).mute(inv.guild_id, false).await;
+    report(ctx, inv, result.map(|_| "Unmuted".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn join(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let channel_id = voice_channel(ctx, inv).await;
+    if let Err(why) = MusicController::new(ctx)
+        .join(inv.guild_id, channel_id)
+        .await
+    {
+        check_msg(inv.reply(ctx, why).await);
+    }
+
+    Ok(())
+}
+
+pub async fn leave(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).leave(inv.guild_id).await;
+    report(ctx, inv, result.map(|_| "Left voice channel".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn play(ctx: &Context, inv: &Invocation, query: String) -> CommandResult {
+    let query = playlist_scope(ctx, inv, query).await;
+    // Stopping also cancels the imports of the previous queue.
+    stop(ctx, inv).await?;
+    enqueue_queries(ctx, inv, vec![query]).await
+}
+
+pub async fn queue(ctx: &Context, inv: &Invocation, query: String) -> CommandResult {
+    let query = playlist_scope(ctx, inv, query).await;
+    enqueue_queries(ctx, inv, vec![query]).await
+}
+
+/// Lists the top results of a YouTube search, and queues the one the author
+/// chooses with a reaction.
+pub async fn search(ctx: &Context, inv: &Invocation, terms: String) -> CommandResult {
+    let results = match youtube::search(&terms, SEARCH_CHOICES.len()).await {
+        Ok(results) if !results.is_empty() => results,
+        Ok(_) => {
+            check_msg(inv.say(ctx, "No results found").await);
+
+            return Ok(());
+        }
+        Err(why) => {
+            println!("Err searching {}: {:?}", terms, why);
+
+            check_msg(inv.say(ctx, format!("{:#}", why)).await);
+
+            return Ok(());
+        }
+    };
+
+    let description = results
+        .iter()
+        .zip(SEARCH_CHOICES)
+        .map(|(result, emoji)| {
+            let duration = result
+                .duration
+                .map(track::format_duration)
+                .unwrap_or_else(|| "live".to_string());
+            format!(
+                "{} {} ({})\n{}",
+                emoji, result.title, duration, result.channel
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut title = format!(
+        "Results for {}",
+        terms.chars().take(SEARCH_TITLE_TERMS).collect::<String>()
+    );
+    if terms.chars().count() > SEARCH_TITLE_TERMS {
+        title.push('…');
+    }
+    let message = match inv
+        .send_embed(ctx, |e| {
+            e.title(title)
+                .description(description)
+                .footer(|f| f.text("React to choose a song"))
+        })
+        .await
+    {
+        Ok(message) => message,
+        Err(why) => {
+            println!("Error sending message: {:?}", why);
+
+            return Ok(());
+        }
+    };
+    for emoji in &SEARCH_CHOICES[..results.len()] {
+        if let Err(why) = message
+            .react(ctx, ReactionType::Unicode(emoji.to_string()))
+            .await
+        {
+            println!("Error reacting to message: {:?}", why);
+        }
+    }
+
+    let choices = results.len();
+    let reaction = message
+        .await_reaction(ctx)
+        .author_id(inv.author)
+        .filter(move |reaction| {
+            SEARCH_CHOICES[..choices].contains(&reaction.emoji.as_data().as_str())
+        })
+        .timeout(Duration::from_secs(SEARCH_CHOICE_SECS))
+        .await;
+    let chosen = reaction.and_then(|action| {
+        let emoji = action.as_inner_ref().emoji.as_data();
+        SEARCH_CHOICES.iter().position(|choice| *choice == emoji)
+    });
+    match chosen.and_then(|i| results.get(i)) {
+        Some(result) => enqueue_queries(ctx, inv, vec![youtube::video_url(&result.id)]).await,
+        None => {
+            check_msg(inv.say(ctx, "No song chosen").await);
+
+            Ok(())
+        }
+    }
+}
+
+/// Asks whether a link to a video within a playlist should play just the
+/// video, or the playlist starting from it. Only the video is played if the
+/// author does not answer.
+async fn playlist_scope(ctx: &Context, inv: &Invocation, query: String) -> String {
+    let link = match youtube::parse_link(&query) {
+        Some(link) => link,
+        None => return query,
+    };
+    let video = match link.video_only() {
+        Some(video) if link.playlist.is_some() => video.url(),
+        _ => return link.url(),
+    };
+
+    let content = format!(
+        "This video is part of a playlist: react with {} to play just the video, or with {} to play the playlist from it.",
+        PLAYLIST_CHOICE_VIDEO, PLAYLIST_CHOICE_PLAYLIST
+    );
+    let message = match inv.say(ctx, content).await {
+        Ok(message) => message,
+        Err(why) => {
+            println!("Error sending message: {:?}", why);
+
+            return video;
+        }
+    };
+    for emoji in [PLAYLIST_CHOICE_VIDEO, PLAYLIST_CHOICE_PLAYLIST] {
+        if let Err(why) = message
+            .react(ctx, ReactionType::Unicode(emoji.to_string()))
+            .await
+        {
+            println!("Error reacting to message: {:?}", why);
+        }
+    }
+    let reaction = message
+        .await_reaction(ctx)
+        .author_id(inv.author)
+        .filter(|reaction| {
+            let emoji = reaction.emoji.as_data();
+            emoji == PLAYLIST_CHOICE_VIDEO || emoji == PLAYLIST_CHOICE_PLAYLIST
+        })
+        .timeout(Duration::from_secs(PLAYLIST_CHOICE_SECS))
+        .await;
+    match reaction {
+        Some(action) if action.as_inner_ref().emoji.as_data() == PLAYLIST_CHOICE_PLAYLIST => {
+            link.url()
+        }
+        _ => video,
+    }
+}
+
+/// Resolves the first query and adds the resulting songs to the queue, joining
+/// the author's voice channel if needed. The other queries are only resolved
+/// in the background, once the first songs play.
+pub async fn enqueue_queries(
+    ctx: &Context,
+    inv: &Invocation,
+    queries: Vec<String>,
+) -> CommandResult {
+    let controller = MusicController::new(ctx);
+    let channel_id = voice_channel(ctx, inv).await;
+    if let Err(why) = controller.connect(inv.guild_id, channel_id).await {
+        check_msg(inv.reply(ctx, why).await);
+
+        return Ok(());
+    }
+    let generation = controller.import_generation(inv.guild_id).await;
+
+    let mut queries = queries.into_iter();
+    let mut songs = VecDeque::new();
+    if let Some(query) = queries.next() {
+        songs.extend(resolve_songs(ctx, inv, &query).await);
+    }
+    songs.extend(queries.map(Song::Pending));
+
+    // The songs which are ready are enqueued right away, along with the first
+    // pending one if needed so that playback starts. The rest is resolved in
+    // the background.
+    let resolvers = resolver::get(ctx).await;
+    let mut sources = Vec::new();
+    let mut failed = Vec::new();
+    while let Some(song) = songs.pop_front() {
+        match song {
+            Song::Ready(source, url) => sources.push((source, url)),
+            Song::Pending(url) if sources.is_empty() => match resolvers.resolve_one(&url).await {
+                Ok(source) => sources.push((source, url)),
+                Err(why) => {
+                    println!("Err resolving {}: {:?}", url, why);
+                    failed.push(url);
+                }
+            },
+            song => {
+                songs.push_front(song);
+                break;
+            }
+        }
+    }
+    if sources.is_empty() {
+        if !failed.is_empty() {
+            check_msg(inv.say(ctx, failures_summary(&failed)).await);
+        }
+        return Ok(());
+    }
+
+    let n = sources.len();
+    let len = match controller
+        .enqueue(inv.guild_id, inv.channel_id, inv.author, sources)
+        .await
+    {
+        Ok(len) => len,
+        Err(why) => {
+            check_msg(inv.say(ctx, why).await);
+
+            return Ok(());
+        }
+    };
+    let pending = songs.len();
+    let mut content = format!("Added {} songs to queue: total length is {}", n, len);
+    if pending > 0 {
+        content.push_str(&format!(", loading {} more songs", pending));
+    }
+    let message = inv.say(ctx, &content).await;
+
+    if pending > 0 {
+        let progress = match message {
+            Ok(message) => Some(ImportProgress { message, content }),
+            Err(why) => {
+                println!("Error sending message: {:?}", why);
+                None
+            }
+        };
+        let import = Import {
+            guild_id: inv.guild_id,
+            channel_id: inv.channel_id,
+            author: inv.author,
+            generation,
+        };
+        tokio::spawn(enqueue_in_background(
+            ctx.clone(),
+            import,
+            songs,
+            failed,
+            progress,
+        ));
+    } else {
+        check_msg(message);
+        if !failed.is_empty() {
+            check_msg(inv.say(ctx, failures_summary(&failed)).await);
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the songs of an import go, and on whose behalf.
+struct Import {
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    author: UserId,
+    /// The generation the import started at, see
+    /// [`MusicController::import_generation`].
+    generation: u64,
+}
+
+/// The message announcing an import, edited as its songs load.
+struct ImportProgress {
+    message: Message,
+    content: String,
+}
+
+impl ImportProgress {
+    async fn report(&mut self, ctx: &Context, loaded: usize, total: usize) {
+        let content = format!("{} ({}/{} loaded)", self.content, loaded, total);
+        if let Err(why) = self.message.edit(ctx, |m| m.content(content)).await {
+            println!("Error editing message: {:?}", why);
+        }
+    }
+}
+
+/// Resolves and enqueues the remaining songs in order, then sends a summary
+/// of the import.
+///
+/// The import stops once cancelled, by stopping the queue or leaving the voice
+/// channel.
+async fn enqueue_in_background(
+    ctx: Context,
+    import: Import,
+    songs: VecDeque<Song>,
+    mut failed: Vec<String>,
+    mut progress: Option<ImportProgress>,
+) {
+    let controller = MusicController::new(&ctx);
+    let resolvers = resolver::get(&ctx).await;
+    let total = songs.len();
+    let mut added = 0;
+    for (i, song) in songs.into_iter().enumerate() {
+        if let Some(progress) = progress.as_mut() {
+            if i > 0 && i % IMPORT_PROGRESS_INTERVAL == 0 {
+                progress.report(&ctx, i, total).await;
+            }
+        }
+
+        let (source, url) = match song {
+            Song::Ready(source, url) => (source, url),
+            Song::Pending(url) => match resolvers.resolve_one(&url).await {
+                Ok(source) => (source, url),
+                Err(why) => {
+                    println!("Err resolving {}: {:?}", url, why);
+                    failed.push(url);
+                    continue;
+                }
+            },
+        };
+        if let Err(why) = controller
+            .extend(
+                import.guild_id,
+                import.channel_id,
+                import.author,
+                vec![(source, url)],
+                import.generation,
+            )
+            .await
+        {
+            println!("Err enqueuing: {:?}", why);
+            return;
+        }
+        added += 1;
+    }
+
+    if let Some(progress) = progress.as_mut() {
+        progress.report(&ctx, total, total).await;
+    }
+    let mut summary = format!("Added {} more songs to queue", added);
+    if !failed.is_empty() {
+        summary.push('\n');
+        summary.push_str(&failures_summary(&failed));
+    }
+    check_msg(import.channel_id.say(&ctx.http, summary).await);
+}
+
+/// Lists the songs of an import which failed to load.
+fn failures_summary(failed: &[String]) -> String {
+    let mut shown = failed
+        .iter()
+        .take(IMPORT_FAILURES_SHOWN)
+        .map(|url| format!("<{}>", url))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if failed.len() > IMPORT_FAILURES_SHOWN {
+        shown.push_str(&format!(
+            " and {} more",
+            failed.len() - IMPORT_FAILURES_SHOWN
+        ));
+    }
+    format!("Failed to load {} songs: {}", failed.len(), shown)
+}
+
+/// Shows the progress of a resolution in a message, sent on the first report
+/// and edited afterwards.
+struct MessageProgress<'a> {
+    ctx: &'a Context,
+    inv: &'a Invocation,
+    message: Mutex<Option<Message>>,
+}
+
+#[async_trait]
+impl Progress for MessageProgress<'_> {
+    async fn report(&self, status: String) {
+        let mut message = self.message.lock().await;
+        match message.as_mut() {
+            Some(message) => {
+                if let Err(why) = message.edit(self.ctx, |m| m.content(status)).await {
+                    println!("Error editing message: {:?}", why);
+                }
+            }
+            None => match self.inv.say(self.ctx, status).await {
+                Ok(sent) => *message = Some(sent),
+                Err(why) => println!("Error sending message: {:?}", why),
+            },
+        }
+    }
+}
+
+/// Finds the songs for a query.
+///
+/// Errors are reported in the channel, and yield no songs.
+async fn resolve_songs(ctx: &Context, inv: &Invocation, query: &str) -> Vec<Song> {
+    let progress = MessageProgress {
+        ctx,
+        inv,
+        message: Mutex::new(None),
+    };
+    match resolver::get(ctx).await.resolve(query, &progress).await {
+        Ok(songs) => songs,
+        Err(why) => {
+            println!("Err resolving {}: {:?}", query, why);
+
+            check_msg(inv.say(ctx, format!("{:#}", why)).await);
+
+            Vec::new()
+        }
+    }
+}
+
+pub async fn restore(ctx: &Context, inv: &Invocation) -> CommandResult {
+    // The restored queue is announced in the channel it was saved from.
+    match restore_saved(ctx, inv.guild_id).await {
+        Ok(0) => check_msg(inv.say(ctx, "Nothing to restore").await),
+        Ok(_) => {}
+        Err(why) => check_msg(inv.say(ctx, why).await),
+    }
+
+    Ok(())
+}
+
+/// Restores the saved queue of `guild_id` and announces it in the channel it
+/// was saved from, returning the number of restored songs.
+pub async fn restore_saved(ctx: &Context, guild_id: GuildId) -> MusicResult<usize> {
+    let (restored, channel_id) = match MusicController::new(ctx).restore(guild_id).await? {
+        Some(restored) => restored,
+        None => return Ok(0),
+    };
+    check_msg(
+        channel_id
+            .say(&ctx.http, format!("Restored {} songs to queue", restored))
+            .await,
+    );
+    idle::activity(ctx, guild_id, channel_id).await;
+
+    Ok(restored)
+}
+
+/// Skips the current song, or votes to skip it when vote-skip is on.
+pub async fn skip(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let controller = MusicController::new(ctx);
+    let percent = settings::get(ctx, inv.guild_id).await.vote_skip;
+    let result = if percent == 0 || dj::skips_directly(ctx, inv.guild_id, inv.author).await {
+        controller.skip(inv.guild_id).await.map(SkipVote::Skipped)
+    } else {
+        controller
+            .vote_skip(inv.guild_id, inv.author, percent)
+            .await
+    };
+    report(
+        ctx,
+        inv,
+        result.map(|vote| match vote {
+            SkipVote::Skipped(len) => format!("Song skipped: {} in queue.", len),
+            SkipVote::Counted { votes, needed } => {
+                format!("Voted to skip: {}/{} votes", votes, needed)
+            }
+        }),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Removes the songs at positions `start` to `end` included.
+pub async fn remove(ctx: &Context, inv: &Invocation, start: usize, end: usize) -> CommandResult {
+    let result = MusicController::new(ctx)
+        .remove(inv.guild_id, inv.channel_id, start, end)
+        .await;
+    report(
+        ctx,
+        inv,
+        result.map(|(removed, len)| format!("Removed {} songs: {} in queue.", removed, len)),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn move_track(ctx: &Context, inv: &Invocation, from: usize, to: usize) -> CommandResult {
+    let result = MusicController::new(ctx)
+        .move_track(inv.guild_id, inv.channel_id, from, to)
+        .await;
+    report(
+        ctx,
+        inv,
+        result.map(|_| format!("Moved song {} to position {}", from, to)),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn skipto(ctx: &Context, inv: &Invocation, index: usize) -> CommandResult {
+    let result = MusicController::new(ctx).skipto(inv.guild_id, index).await;
+    report(
+        ctx,
+        inv,
+        result.map(|len| format!("Skipped {} songs: {} in queue.", index, len)),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn removedupes(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx)
+        .remove_duplicates(inv.guild_id, inv.channel_id)
+        .await;
+    report(
+        ctx,
+        inv,
+        result.map(|(removed, len)| {
+            format!("Removed {} duplicate songs: {} in queue.", removed, len)
+        }),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Sets the loop mode, or reports the current one if `mode` is `None`.
+pub async fn loop_mode(ctx: &Context, inv: &Invocation, mode: Option<LoopMode>) -> CommandResult {
+    let controller = MusicController::new(ctx);
+    let result = match mode {
+        Some(mode) => controller
+            .set_loop_mode(inv.guild_id, mode)
+            .await
+            .map(|_| format!("Loop mode set to {:?}", mode)),
+        None => Ok(format!(
+            "Loop mode is {:?}",
+            controller.loop_mode(inv.guild_id).await
+        )),
+    };
+    report(ctx, inv, result).await;
+
+    Ok(())
+}
+
+/// Sets the volume in percent, or reports the current one if `volume` is `None`.
+pub async fn volume(ctx: &Context, inv: &Invocation, volume: Option<u32>) -> CommandResult {
+    let controller = MusicController::new(ctx);
+    let result = match volume {
+        Some(volume) => controller
+            .set_volume(inv.guild_id, volume)
+            .await
+            .map(|_| format!("Volume set to {}%", volume)),
+        None => Ok(format!(
+            "Volume is {}%",
+            controller.volume(inv.guild_id).await
+        )),
+    };
+    report(ctx, inv, result).await;
+
+    Ok(())
+}
+
+/// Seeks the current track to the position computed by `target` from the
+/// current position.
+async fn seek_current<F>(ctx: &Context, inv: &Invocation, target: F) -> CommandResult
+where
+    F: FnOnce(Duration) -> Duration,
+{
+    let result = MusicController::new(ctx).seek(inv.guild_id, target).await;
+    report(
+        ctx,
+        inv,
+        result.map(|(position, duration)| {
+            format!(
+                "Seeked to {} / {}",
+                track::format_duration(position),
+                track::format_duration(duration)
+            )
+        }),
+    )
+    .await;
+
+    Ok(())
+}
+
+pub async fn seek(ctx: &Context, inv: &Invocation, position: Duration) -> CommandResult {
+    seek_current(ctx, inv, |_| position).await
+}
+
+pub async fn ff(ctx: &Context, inv: &Invocation, secs: u64) -> CommandResult {
+    seek_current(ctx, inv, |position| {
+        position.saturating_add(Duration::from_secs(secs))
+    })
+    .await
+}
+
+pub async fn rw(ctx: &Context, inv: &Invocation, secs: u64) -> CommandResult {
+    seek_current(ctx, inv, |position| {
+        position.saturating_sub(Duration::from_secs(secs))
+    })
+    .await
+}
+
+pub async fn shuffle(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx)
+        .shuffle(inv.guild_id, inv.channel_id)
+        .await;
+    report(ctx, inv, result.map(|_| "Shuffled the queue".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn pause(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).pause(inv.guild_id).await;
+    report(ctx, inv, result.map(|_| "Paused the queue".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn resume(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).resume(inv.guild_id).await;
+    report(ctx, inv, result.map(|_| "Resumed the queue".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn stop(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let result = MusicController::new(ctx).stop(inv.guild_id).await;
+    report(ctx, inv, result.map(|_| "Queue cleared.".to_string())).await;
+
+    Ok(())
+}
+
+pub async fn nowplaying(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let queue = MusicController::new(ctx).queue(inv.guild_id).await;
+    let current = match queue.first() {
+        Some(current) => current,
+        None => {
+            check_msg(inv.say(ctx, "Nothing is playing").await);
+
+            return Ok(());
+        }
+    };
+
+    let metadata = current.metadata();
+    let position = current
+        .get_info()
+        .await
+        .map(|info| info.position)
+        .unwrap_or_default();
+    let progress = match metadata.duration {
+        Some(duration) => format!(
+            "{} {} / {}",
+            track::progress_bar(position, duration, NOW_PLAYING_BAR_WIDTH),
+            track::format_duration(position),
+            track::format_duration(duration)
+        ),
+        None => format!("{} (live)", track::format_duration(position)),
+    };
+    let requester = track::request_of(current)
+        .await
+        .map(|request| request.requester.mention().to_string())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let uploader = metadata
+        .channel
+        .clone()
+        .or_else(|| metadata.artist.clone())
+        .unwrap_or_else(|| "Unknown".to_string());
+    let up_next = queue
+        .iter()
+        .skip(1)
+        .take(NOW_PLAYING_UP_NEXT)
+        .enumerate()
+        .map(|(i, handle)| format!("{}. {}", i + 1, track::title_of(handle)))
+        .collect::<Vec<_>>();
+
+    check_msg(
+        inv.send_embed(ctx, |e| {
+            e.title(track::title_of(current));
+            if let Some(url) = &metadata.source_url {
+                e.url(url);
+            }
+            if let Some(thumbnail) = &metadata.thumbnail {
+                e.thumbnail(thumbnail);
+            }
+            e.description(progress);
+            e.field("Uploader", uploader, true);
+            e.field("Requested by", requester, true);
+            if !up_next.is_empty() {
+                e.field("Up next", up_next.join("\n"), false);
+            }
+            e
+        })
+        .await,
+    );
+
+    Ok(())
+}
+
+/// Shows the queue, starting at `page` and navigable with reactions.
+pub async fn list(ctx: &Context, inv: &Invocation, page: Option<usize>) -> CommandResult {
+    let queue = MusicController::new(ctx).queue(inv.guild_id).await;
+    if queue.is_empty() {
+        check_msg(inv.say(ctx, "The queue is empty").await);
+
+        return Ok(());
+    }
+
+    let position = queue[0]
+        .get_info()
+        .await
+        .map(|info| info.position)
+        .unwrap_or_default();
+    let mut remaining = Duration::default();
+    let mut entries = Vec::with_capacity(queue.len());
+    for (i, handle) in queue.iter().enumerate() {
+        let duration = handle.metadata().duration;
+        if let Some(duration) = duration {
+            remaining += if i == 0 {
+                duration.saturating_sub(position)
+            } else {
+                duration
+            };
+        }
+        let requester = track::request_of(handle)
+            .await
+            .map(|request| request.requester.mention().to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+        let index = if i == 0 {
+            "Playing".to_string()
+        } else {
+            i.to_string()
+        };
+        entries.push(format!(
+            "`{}` {} [{}] - {}",
+            index,
+            track::title_of(handle),
+            duration
+                .map(track::format_duration)
+                .unwrap_or_else(|| "live".to_string()),
+            requester
+        ));
+    }
+
+    let pages = entries.len().div_ceil(LIST_PAGE_SIZE);
+    let mut page = page.unwrap_or(1).clamp(1, pages);
+    let footer = |page: usize| {
+        format!(
+            "Page {}/{} - {} songs - {} remaining",
+            page,
+            pages,
+            entries.len(),
+            track::format_duration(remaining)
+        )
+    };
+    let description = |page: usize| {
+        entries
+            .iter()
+            .skip((page - 1) * LIST_PAGE_SIZE)
+            .take(LIST_PAGE_SIZE)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let mut message = match inv
+        .send_embed(ctx, |e| {
+            e.title("Queue")
+                .description(description(page))
+                .footer(|f| f.text(footer(page)))
+        })
+        .await
+    {
+        Ok(message) => message,
+        Err(why) => {
+            println!("Error sending message: {:?}", why);
+
+            return Ok(());
+        }
+    };
+    if pages == 1 {
+        return Ok(());
+    }
+    for emoji in [LIST_PREVIOUS, LIST_NEXT] {
+        if let Err(why) = message
+            .react(ctx, ReactionType::Unicode(emoji.to_string()))
+            .await
+        {
+            println!("Error reacting to message: {:?}", why);
+        }
+    }
+
+    // Both adding and removing a reaction turn the page, so that users don't
+    // have to remove their reaction before pressing it again.
+    let mut reactions = message
+        .await_reactions(ctx)
+        .author_id(inv.author)
+        .added(true)
+        .removed(true)
+        .timeout(Duration::from_secs(LIST_NAVIGATION_SECS))
+        .await;
+    while let Some(action) = reactions.next().await {
+        let new_page = match action.as_inner_ref().emoji.as_data().as_str() {
+            LIST_PREVIOUS if page > 1 => page - 1,
+            LIST_NEXT if page < pages => page + 1,
+            _ => continue,
+        };
+        page = new_page;
+        let edited = message
+            .edit(ctx, |m| {
+                m.embed(|e| {
+                    e.title("Queue")
+                        .description(description(page))
+                        .footer(|f| f.text(footer(page)))
+                })
+            })
+            .await;
+        if let Err(why) = edited {
+            println!("Error editing message: {:?}", why);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,706 @@
+//! Playback logic behind the commands, independent of how they are invoked
+//! and of how their results are shown.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use rand::{seq::SliceRandom, thread_rng};
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+    prelude::Mutex,
+};
+use songbird::{
+    error::JoinError,
+    input::restartable::Restartable,
+    tracks::{PlayMode, TrackError, TrackHandle},
+    Call, Songbird,
+};
+use thiserror::Error;
+
+use crate::{
+    persist, resolver, session, settings, state, track, LoopMode, TrackRequest, MAX_VOLUME,
+};
+
+#[derive(Debug, Error)]
+pub enum MusicError {
+    #[error("Not in a voice channel to play in")]
+    NotConnected,
+    #[error("You are not in a voice channel")]
+    AuthorNotInVoice,
+    #[error("Nothing is playing")]
+    NothingPlaying,
+    #[error("There are no upcoming songs in the queue")]
+    NoUpcoming,
+    #[error("Expected a position between 1 and {max}")]
+    OutOfRange { max: usize },
+    #[error("Cannot seek in a live stream")]
+    NotSeekable,
+    #[error("The current track cannot be looped")]
+    NotLoopable,
+    #[error("You are not listening in the voice channel")]
+    NotListening,
+    #[error("You already voted to skip this song")]
+    AlreadyVoted,
+    #[error("The import was cancelled")]
+    ImportCancelled,
+    #[error("Expected a volume between 0 and {max}")]
+    InvalidVolume { max: u32 },
+    #[error("Failed: {0}")]
+    Join(#[from] JoinError),
+    #[error("Failed: {0}")]
+    Track(#[from] TrackError),
+    #[error("Failed: {0}")]
+    Restore(#[from] anyhow::Error),
+}
+
+impl MusicError {
+    /// Error for a queue position outside of `1..len`.
+    fn out_of_range(len: usize) -> Self {
+        match len {
+            0 | 1 => MusicError::NoUpcoming,
+            _ => MusicError::OutOfRange { max: len - 1 },
+        }
+    }
+}
+
+pub type MusicResult<T> = Result<T, MusicError>;
+
+/// Whether `index` is the position of an upcoming song in a queue of `len`
+/// songs, the current one being at position 0.
+fn is_upcoming(len: usize, index: usize) -> bool {
+    index != 0 && index < len
+}
+
+/// Whether the positions `start` to `end` included are those of upcoming
+/// songs in a queue of `len` songs.
+fn is_upcoming_range(len: usize, start: usize, end: usize) -> bool {
+    start <= end && is_upcoming(len, start) && is_upcoming(len, end)
+}
+
+/// Moves the item at `from` to `to`, shifting the items in between.
+fn move_item<T>(items: &mut VecDeque<T>, from: usize, to: usize) {
+    if let Some(item) = items.remove(from) {
+        items.insert(to, item);
+    }
+}
+
+/// Number of votes needed to skip a song, for `percent` of `listeners`.
+fn votes_needed(listeners: usize, percent: u32) -> usize {
+    (listeners * percent as usize).div_ceil(100).max(1)
+}
+
+/// The outcome of a vote to skip the current song.
+pub enum SkipVote {
+    /// Enough listeners voted, and the song was skipped, leaving this many
+    /// songs in the queue.
+    Skipped(usize),
+    /// The vote was counted, but more are needed.
+    Counted { votes: usize, needed: usize },
+}
+
+/// The volume to play tracks at in `guild_id`.
+pub async fn guild_volume(ctx: &Context, guild_id: GuildId) -> f32 {
+    settings::get(ctx, guild_id).await.volume as f32 / 100.0
+}
+
+/// Controls the calls and queues of every guild.
+pub struct MusicController {
+    ctx: Context,
+}
+
+impl MusicController {
+    pub fn new(ctx: &Context) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn manager(&self) -> Arc<Songbird> {
+        songbird::get(&self.ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone()
+    }
+
+    /// The call of `guild_id`, if the bot is in a voice channel there.
+    async fn call(&self, guild_id: GuildId) -> MusicResult<Arc<Mutex<Call>>> {
+        self.manager()
+            .await
+            .get(guild_id)
+            .ok_or(MusicError::NotConnected)
+    }
+
+    /// Joins `channel_id`, the voice channel of the author.
+    pub async fn join(&self, guild_id: GuildId, channel_id: Option<ChannelId>) -> MusicResult<()> {
+        let channel_id = channel_id.ok_or(MusicError::AuthorNotInVoice)?;
+        let (_, joined) = self.manager().await.join(guild_id, channel_id).await;
+        Ok(joined?)
+    }
+
+    /// Joins `channel_id` unless the bot is already in a voice channel.
+    pub async fn connect(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+    ) -> MusicResult<()> {
+        if self.call(guild_id).await.is_ok() {
+            return Ok(());
+        }
+        self.join(guild_id, channel_id).await
+    }
+
+    pub async fn leave(&self, guild_id: GuildId) -> MusicResult<()> {
+        self.call(guild_id).await?;
+        persist::clear(guild_id);
+        state::update(&self.ctx, guild_id, |state| {
+            state.paused_alone = false;
+            state.listeners.clear();
+        })
+        .await;
+        self.cancel_imports(guild_id).await;
+        session::end(&self.ctx, guild_id).await;
+        Ok(self.manager().await.remove(guild_id).await?)
+    }
+
+    /// Deafens or undeafens the bot, returning whether anything changed.
+    pub async fn deafen(&self, guild_id: GuildId, deaf: bool) -> MusicResult<bool> {
+        let handler_lock = self.call(guild_id).await?;
+        let mut handler = handler_lock.lock().await;
+        if deaf && handler.is_deaf() {
+            return Ok(false);
+        }
+        handler.deafen(deaf).await?;
+        Ok(true)
+    }
+
+    /// Mutes or unmutes the bot, returning whether anything changed.
+    pub async fn mute(&self, guild_id: GuildId, mute: bool) -> MusicResult<bool> {
+        let handler_lock = self.call(guild_id).await?;
+        let mut handler = handler_lock.lock().await;
+        if mute && handler.is_mute() {
+            return Ok(false);
+        }
+        handler.mute(mute).await?;
+        Ok(true)
+    }
+
+    /// Adds sources to the queue on behalf of `requester`, returning the new
+    /// length of the queue.
+    pub async fn enqueue(
+        &self,
+        guild_id: GuildId,
+        text_channel: ChannelId,
+        requester: UserId,
+        sources: Vec<(Restartable, String)>,
+    ) -> MusicResult<usize> {
+        let handler_lock = self.call(guild_id).await?;
+        let mut handler = handler_lock.lock().await;
+
+        // The session's handlers must be in place before the first track starts.
+        session::start(&self.ctx, guild_id, &mut handler, text_channel).await;
+        let volume = guild_volume(&self.ctx, guild_id).await;
+        for (source, url) in sources {
+            let request = TrackRequest {
+                requester,
+                channel: text_channel,
+                url,
+            };
+            track::enqueue(&mut handler, source, request, volume).await;
+        }
+
+        persist::save_call(guild_id, &handler, text_channel).await;
+
+        Ok(handler.queue().len())
+    }
+
+    /// Adds more sources to a queue started with [`MusicController::enqueue`],
+    /// unless the import they belong to, started at `generation`, was
+    /// cancelled since.
+    pub async fn extend(
+        &self,
+        guild_id: GuildId,
+        text_channel: ChannelId,
+        requester: UserId,
+        sources: Vec<(Restartable, String)>,
+        generation: u64,
+    ) -> MusicResult<usize> {
+        let handler_lock = self.call(guild_id).await?;
+        let mut handler = handler_lock.lock().await;
+        if self.import_generation(guild_id).await != generation {
+            return Err(MusicError::ImportCancelled);
+        }
+
+        let volume = guild_volume(&self.ctx, guild_id).await;
+        for (source, url) in sources {
+            let request = TrackRequest {
+                requester,
+                channel: text_channel,
+                url,
+            };
+            track::enqueue(&mut handler, source, request, volume).await;
+        }
+        persist::save_call(guild_id, &handler, text_channel).await;
+
+        Ok(handler.queue().len())
+    }
+
+    /// The generation of the imports started now, which lasts until they are
+    /// cancelled.
+    pub async fn import_generation(&self, guild_id: GuildId) -> u64 {
+        state::get(&self.ctx, guild_id).await.import_generation
+    }
+
+    /// Cancels the imports loading songs in the background.
+    async fn cancel_imports(&self, guild_id: GuildId) {
+        state::update(&self.ctx, guild_id, |state| {
+            state.import_generation = state.import_generation.wrapping_add(1)
+        })
+        .await;
+    }
+
+    /// Rejoins the saved voice channel of `guild_id` and rebuilds its queue,
+    /// returning the number of restored songs and the channel the queue was
+    /// saved from, or `None` if there is nothing to restore.
+    ///
+    /// Songs are resolved one at a time without holding the call, so that the
+    /// first ones play while the others load.
+    pub async fn restore(&self, guild_id: GuildId) -> MusicResult<Option<(usize, ChannelId)>> {
+        let snapshot = match persist::load(guild_id)? {
+            Some(snapshot) if !snapshot.tracks.is_empty() => snapshot,
+            _ => return Ok(None),
+        };
+        if let Ok(handler_lock) = self.call(guild_id).await {
+            if !handler_lock.lock().await.queue().is_empty() {
+                return Ok(None);
+            }
+        }
+
+        let (handler_lock, joined) = self
+            .manager()
+            .await
+            .join(guild_id, ChannelId(snapshot.voice_channel))
+            .await;
+        joined?;
+        let text_channel = ChannelId(snapshot.text_channel);
+        session::start(
+            &self.ctx,
+            guild_id,
+            &mut *handler_lock.lock().await,
+            text_channel,
+        )
+        .await;
+
+        let generation = self.import_generation(guild_id).await;
+        let resolvers = resolver::get(&self.ctx).await;
+        let mut restored = 0;
+        for (index, saved) in snapshot.tracks.into_iter().enumerate() {
+            let source = match resolvers.resolve_one(&saved.url).await {
+                Ok(source) => source,
+                Err(why) => {
+                    println!("Err starting source: {:?}", why);
+                    continue;
+                }
+            };
+            let mut handler = handler_lock.lock().await;
+            // Stopping the queue or leaving cancels the rest of the restore.
+            if self.import_generation(guild_id).await != generation {
+                return Ok(Some((restored, text_channel)));
+            }
+            let request = TrackRequest {
+                requester: UserId(saved.requester),
+                channel: saved.channel.map(ChannelId).unwrap_or(text_channel),
+                url: saved.url,
+            };
+            let volume = guild_volume(&self.ctx, guild_id).await;
+            let handle = track::enqueue(&mut handler, source, request, volume).await;
+            // The position is that of the first saved track, which may have failed.
+            if index == 0 && snapshot.position > Duration::default() {
+                let _ = handle.seek_time(snapshot.position);
+            }
+            restored += 1;
+        }
+
+        let handler = handler_lock.lock().await;
+        persist::save_call(guild_id, &handler, text_channel).await;
+        Ok(Some((restored, text_channel)))
+    }
+
+    /// The tracks of the queue, starting with the current one.
+    pub async fn queue(&self, guild_id: GuildId) -> Vec<TrackHandle> {
+        match self.call(guild_id).await {
+            Ok(handler_lock) => handler_lock.lock().await.queue().current_queue(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    async fn current(&self, guild_id: GuildId) -> MusicResult<TrackHandle> {
+        let handler_lock = self.call(guild_id).await?;
+        let current = handler_lock.lock().await.queue().current();
+        current.ok_or(MusicError::NothingPlaying)
+    }
+
+    /// The voice channel of the bot.
+    pub async fn voice_channel(&self, guild_id: GuildId) -> MusicResult<ChannelId> {
+        let handler_lock = self.call(guild_id).await?;
+        let channel_id = handler_lock
+            .lock()
+            .await
+            .current_channel()
+            .ok_or(MusicError::NotConnected)?;
+        Ok(ChannelId(channel_id.0))
+    }
+
+    /// The members listening in the voice channel of the bot, bots excluded.
+    pub async fn listeners(&self, guild_id: GuildId) -> MusicResult<Vec<UserId>> {
+        let channel_id = self.voice_channel(guild_id).await?;
+        let guild = match guild_id.to_guild_cached(&self.ctx.cache).await {
+            Some(guild) => guild,
+            None => return Ok(Vec::new()),
+        };
+        let listeners = guild
+            .voice_states
+            .values()
+            .filter(|voice| voice.channel_id == Some(channel_id))
+            .filter(|voice| {
+                let member = guild.members.get(&voice.user_id).or(voice.member.as_ref());
+                !member.is_some_and(|member| member.user.bot)
+            })
+            .map(|voice| voice.user_id)
+            .collect();
+        Ok(listeners)
+    }
+
+    /// Counts the vote of `voter` to skip the current song, which is skipped
+    /// once `percent` of the listeners voted for it.
+    pub async fn vote_skip(
+        &self,
+        guild_id: GuildId,
+        voter: UserId,
+        percent: u32,
+    ) -> MusicResult<SkipVote> {
+        let current = self.current(guild_id).await?;
+        let listeners = self.listeners(guild_id).await?;
+        if !listeners.contains(&voter) {
+            return Err(MusicError::NotListening);
+        }
+
+        let votes = state::update(&self.ctx, guild_id, |state| {
+            // Votes only count for the track they were cast on.
+            let track = state.skip_votes_track.as_ref().map(|track| track.uuid());
+            if track != Some(current.uuid()) {
+                state.skip_votes.clear();
+                state.skip_votes_track = Some(current.clone());
+            }
+            // Votes of listeners who left don't count.
+            state.skip_votes.retain(|user| listeners.contains(user));
+            state
+                .skip_votes
+                .insert(voter)
+                .then_some(state.skip_votes.len())
+        })
+        .await
+        .ok_or(MusicError::AlreadyVoted)?;
+        let needed = votes_needed(listeners.len(), percent);
+        if votes < needed {
+            return Ok(SkipVote::Counted { votes, needed });
+        }
+        Ok(SkipVote::Skipped(self.skip(guild_id).await?))
+    }
+
+    /// Skips the current song, returning the number of songs left.
+    pub async fn skip(&self, guild_id: GuildId) -> MusicResult<usize> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        let _ = queue.skip();
+        Ok(queue.len())
+    }
+
+    /// Stops playing and empties the queue.
+    pub async fn stop(&self, guild_id: GuildId) -> MusicResult<()> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        for handle in queue.current_queue() {
+            track::retire(&handle).await;
+        }
+        queue.stop();
+        // Stopped while the call is locked, so that no import adds songs after.
+        self.cancel_imports(guild_id).await;
+        persist::clear(guild_id);
+        Ok(())
+    }
+
+    pub async fn pause(&self, guild_id: GuildId) -> MusicResult<()> {
+        let handler_lock = self.call(guild_id).await?;
+        let _ = handler_lock.lock().await.queue().pause();
+        Ok(())
+    }
+
+    /// Whether the current song is paused.
+    pub async fn is_paused(&self, guild_id: GuildId) -> bool {
+        match self.current(guild_id).await {
+            Ok(current) => current
+                .get_info()
+                .await
+                .is_ok_and(|info| info.playing == PlayMode::Pause),
+            Err(_) => false,
+        }
+    }
+
+    pub async fn resume(&self, guild_id: GuildId) -> MusicResult<()> {
+        let handler_lock = self.call(guild_id).await?;
+        let _ = handler_lock.lock().await.queue().resume();
+        Ok(())
+    }
+
+    pub async fn shuffle(&self, guild_id: GuildId, text_channel: ChannelId) -> MusicResult<()> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        let _ = queue.pause();
+        queue.modify_queue(|q| q.make_contiguous().shuffle(&mut thread_rng()));
+        let _ = queue.resume();
+        persist::save_call(guild_id, &handler, text_channel).await;
+        Ok(())
+    }
+
+    /// Removes the songs at positions `start` to `end` included, returning
+    /// how many were removed and how many are left.
+    pub async fn remove(
+        &self,
+        guild_id: GuildId,
+        text_channel: ChannelId,
+        start: usize,
+        end: usize,
+    ) -> MusicResult<(usize, usize)> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        if !is_upcoming_range(queue.len(), start, end) {
+            return Err(MusicError::out_of_range(queue.len()));
+        }
+
+        let removed = queue.modify_queue(|q| q.drain(start..=end).collect::<Vec<_>>());
+        for track in &removed {
+            track::discard(track).await;
+        }
+        persist::save_call(guild_id, &handler, text_channel).await;
+
+        Ok((removed.len(), queue.len()))
+    }
+
+    pub async fn move_track(
+        &self,
+        guild_id: GuildId,
+        text_channel: ChannelId,
+        from: usize,
+        to: usize,
+    ) -> MusicResult<()> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        if !is_upcoming(queue.len(), from) || !is_upcoming(queue.len(), to) {
+            return Err(MusicError::out_of_range(queue.len()));
+        }
+
+        queue.modify_queue(|q| move_item(q, from, to));
+        persist::save_call(guild_id, &handler, text_channel).await;
+
+        Ok(())
+    }
+
+    /// Skips to the song at `index`, returning the number of songs left after it.
+    pub async fn skipto(&self, guild_id: GuildId, index: usize) -> MusicResult<usize> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+        if !is_upcoming(queue.len(), index) {
+            return Err(MusicError::out_of_range(queue.len()));
+        }
+
+        // Drop the songs between the current one and the target, then skip
+        // the current one so that the target starts playing.
+        let removed = queue.modify_queue(|q| q.drain(1..index).collect::<Vec<_>>());
+        for track in &removed {
+            track::discard(track).await;
+        }
+        let _ = queue.skip();
+
+        Ok(queue.len() - 1)
+    }
+
+    /// Removes the songs which are already in the queue, returning how many
+    /// were removed and how many are left.
+    pub async fn remove_duplicates(
+        &self,
+        guild_id: GuildId,
+        text_channel: ChannelId,
+    ) -> MusicResult<(usize, usize)> {
+        let handler_lock = self.call(guild_id).await?;
+        let handler = handler_lock.lock().await;
+        let queue = handler.queue();
+
+        let mut seen = HashSet::new();
+        let mut duplicates = HashSet::new();
+        for handle in queue.current_queue() {
+            if let Some(url) = track::source_url(&handle).await {
+                if !seen.insert(url) {
+                    duplicates.insert(handle.uuid());
+                }
+            }
+        }
+
+        let removed = queue.modify_queue(|q| {
+            let mut removed = Vec::new();
+            // The current track is never a duplicate, as it comes first.
+            let mut i = 1;
+            while i < q.len() {
+                if duplicates.contains(&q[i].uuid()) {
+                    removed.extend(q.remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+            removed
+        });
+        for track in &removed {
+            track::discard(track).await;
+        }
+        persist::save_call(guild_id, &handler, text_channel).await;
+
+        Ok((removed.len(), queue.len()))
+    }
+
+    pub async fn loop_mode(&self, guild_id: GuildId) -> LoopMode {
+        state::get(&self.ctx, guild_id).await.loop_mode
+    }
+
+    pub async fn set_loop_mode(&self, guild_id: GuildId, mode: LoopMode) -> MusicResult<()> {
+        if let Ok(current) = self.current(guild_id).await {
+            let looped = if mode == LoopMode::Track {
+                current.enable_loop()
+            } else {
+                current.disable_loop()
+            };
+            if looped.is_err() && mode == LoopMode::Track {
+                return Err(MusicError::NotLoopable);
+            }
+        }
+
+        state::update(&self.ctx, guild_id, |state| state.loop_mode = mode).await;
+        Ok(())
+    }
+
+    /// The volume of `guild_id`, in percent.
+    pub async fn volume(&self, guild_id: GuildId) -> u32 {
+        settings::get(&self.ctx, guild_id).await.volume
+    }
+
+    pub async fn set_volume(&self, guild_id: GuildId, volume: u32) -> MusicResult<()> {
+        if volume > MAX_VOLUME {
+            return Err(MusicError::InvalidVolume { max: MAX_VOLUME });
+        }
+        settings::update(&self.ctx, guild_id, |settings| settings.volume = volume).await;
+        self.apply_volume(guild_id).await;
+        Ok(())
+    }
+
+    /// Applies the volume of `guild_id` to the current track. Queued tracks
+    /// pick it up when they start.
+    pub async fn apply_volume(&self, guild_id: GuildId) {
+        if let Ok(current) = self.current(guild_id).await {
+            let _ = current.set_volume(guild_volume(&self.ctx, guild_id).await);
+        }
+    }
+
+    /// Seeks the current track to the position computed by `target` from the
+    /// current position, returning the new position and the track duration.
+    pub async fn seek<F>(&self, guild_id: GuildId, target: F) -> MusicResult<(Duration, Duration)>
+    where
+        F: FnOnce(Duration) -> Duration,
+    {
+        let current = self.current(guild_id).await?;
+
+        // Live streams have no duration, and cannot be seeked.
+        let duration = match current.metadata().duration {
+            Some(duration) if current.is_seekable() => duration,
+            _ => return Err(MusicError::NotSeekable),
+        };
+
+        let position = current
+            .get_info()
+            .await
+            .map(|info| info.position)
+            .unwrap_or_default();
+        let position = target(position).min(duration);
+        current.seek_time(position)?;
+
+        Ok((position, duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn out_of_range_without_upcoming_songs() {
+        assert!(matches!(
+            MusicError::out_of_range(0),
+            MusicError::NoUpcoming
+        ));
+        assert!(matches!(
+            MusicError::out_of_range(1),
+            MusicError::NoUpcoming
+        ));
+        assert!(matches!(
+            MusicError::out_of_range(5),
+            MusicError::OutOfRange { max: 4 }
+        ));
+        assert_eq!(
+            MusicError::out_of_range(5).to_string(),
+            "Expected a position between 1 and 4"
+        );
+    }
+
+    #[test]
+    fn positions_of_upcoming_songs() {
+        assert!(is_upcoming(5, 1));
+        assert!(is_upcoming(5, 4));
+        assert!(!is_upcoming(5, 0));
+        assert!(!is_upcoming(5, 5));
+        assert!(!is_upcoming(1, 1));
+    }
+
+    #[test]
+    fn ranges_of_upcoming_songs() {
+        assert!(is_upcoming_range(5, 1, 4));
+        assert!(is_upcoming_range(5, 2, 2));
+        assert!(!is_upcoming_range(5, 0, 2));
+        assert!(!is_upcoming_range(5, 3, 2));
+        assert!(!is_upcoming_range(5, 2, 5));
+    }
+
+    #[test]
+    fn move_item_shifts_the_others() {
+        let mut items = VecDeque::from(vec![0, 1, 2, 3, 4]);
+        move_item(&mut items, 1, 3);
+        assert_eq!(items, [0, 2, 3, 1, 4]);
+        move_item(&mut items, 4, 1);
+        assert_eq!(items, [0, 4, 2, 3, 1]);
+        move_item(&mut items, 2, 2);
+        assert_eq!(items, [0, 4, 2, 3, 1]);
+    }
+
+    #[test]
+    fn votes_needed_rounds_up() {
+        assert_eq!(votes_needed(3, 50), 2);
+        assert_eq!(votes_needed(4, 50), 2);
+        assert_eq!(votes_needed(1, 100), 1);
+        assert_eq!(votes_needed(10, 1), 1);
+        assert_eq!(votes_needed(0, 50), 1);
+    }
+}
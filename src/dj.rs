@@ -0,0 +1,157 @@
+//! Commands restricted to the DJ role.
+
+use std::ptr;
+
+use serenity::{
+    client::Context,
+    framework::standard::{
+        macros::{check, hook},
+        Args, Command, CommandOptions, DispatchError, Reason,
+    },
+    model::{
+        channel::Message,
+        id::{GuildId, RoleId, UserId},
+    },
+};
+
+use crate::{check_msg, controller::MusicController, settings, track, GENERAL_GROUP};
+
+/// Message shown to users who can't run a DJ command.
+pub const DJ_ONLY: &str = "Only DJs can use this command";
+
+// Restricts the commands chosen in `dj_commands` to the DJ role.
+#[check]
+#[name = "DJ"]
+async fn dj_check(
+    ctx: &Context,
+    msg: &Message,
+    args: &mut Args,
+    options: &CommandOptions,
+) -> Result<(), Reason> {
+    // Subcommands are checked with their own options, but restricted along
+    // with their parent command.
+    let (guild_id, command) = match (msg.guild_id, command_name(options)) {
+        (Some(guild_id), Some(command)) => (guild_id, command),
+        _ => return Ok(()),
+    };
+    if allowed(
+        ctx,
+        guild_id,
+        msg.author.id,
+        command,
+        track::parse_range(args.rest()),
+    )
+    .await
+    {
+        Ok(())
+    } else {
+        Err(Reason::User(DJ_ONLY.to_string()))
+    }
+}
+
+/// The name of the command of the group with these `options`, or with a
+/// subcommand with these `options`.
+fn command_name(options: &CommandOptions) -> Option<&'static str> {
+    fn owns(command: &Command, options: &CommandOptions) -> bool {
+        ptr::eq(command.options, options)
+            || command
+                .options
+                .sub_commands
+                .iter()
+                .any(|sub| owns(sub, options))
+    }
+    GENERAL_GROUP
+        .options
+        .commands
+        .iter()
+        .find(|command| owns(command, options))
+        .and_then(|command| command.options.names.first().copied())
+}
+
+/// Tells users why a command was refused by a check.
+#[hook]
+pub async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError) {
+    if let DispatchError::CheckFailed(_, Reason::User(reason)) = error {
+        check_msg(msg.channel_id.say(&ctx.http, reason).await);
+    }
+}
+
+/// Whether `author` may run `command`.
+///
+/// DJ commands need the DJ role or the Manage Server permission, except for
+/// skipping or removing (the `range` of positions) songs the author requested.
+/// When vote-skip is on, anyone may vote with `skip`.
+pub async fn allowed(
+    ctx: &Context,
+    guild_id: GuildId,
+    author: UserId,
+    command: &str,
+    range: Option<(usize, usize)>,
+) -> bool {
+    let settings = settings::get(ctx, guild_id).await;
+    let restricted =
+        settings.dj_role.is_some() && settings.dj_commands.iter().any(|c| c == command);
+    if !restricted || (command == "skip" && settings.vote_skip > 0) {
+        return true;
+    }
+    if is_dj(ctx, guild_id, author).await {
+        return true;
+    }
+
+    let own_range = match command {
+        "skip" => Some((0, 0)),
+        "remove" => range,
+        _ => None,
+    };
+    match own_range {
+        Some((start, end)) => requested_by(ctx, guild_id, author, start, end).await,
+        None => false,
+    }
+}
+
+/// Whether `author` has the DJ role or the Manage Server permission.
+pub async fn is_dj(ctx: &Context, guild_id: GuildId, author: UserId) -> bool {
+    let guild = match guild_id.to_guild_cached(&ctx.cache).await {
+        Some(guild) => guild,
+        None => return false,
+    };
+    if let Some(role) = settings::get(ctx, guild_id).await.dj_role {
+        if let Ok(member) = guild.member(ctx, author).await {
+            if member.roles.contains(&RoleId(role)) {
+                return true;
+            }
+        }
+    }
+    guild
+        .member_permissions(ctx, author)
+        .await
+        .is_ok_and(|permissions| permissions.manage_guild())
+}
+
+/// Whether `author` skips without a vote: DJs, and the requester of the
+/// current song.
+pub async fn skips_directly(ctx: &Context, guild_id: GuildId, author: UserId) -> bool {
+    is_dj(ctx, guild_id, author).await || requested_by(ctx, guild_id, author, 0, 0).await
+}
+
+/// Whether the songs between `start` and `end` were all requested by `author`.
+async fn requested_by(
+    ctx: &Context,
+    guild_id: GuildId,
+    author: UserId,
+    start: usize,
+    end: usize,
+) -> bool {
+    let queue = MusicController::new(ctx).queue(guild_id).await;
+    let tracks = match queue.get(start..=end) {
+        Some(tracks) if start <= end => tracks,
+        _ => return false,
+    };
+    for handle in tracks {
+        match track::request_of(handle).await {
+            Some(request) if request.requester == author => {}
+            _ => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,212 @@
+//! Leaving voice channels once the bot is idle, following the policy set in
+//! the settings of each guild.
+//!
+//! Each guild has a single timer, which is replaced whenever something may
+//! change when the bot becomes idle.
+
+use std::time::{Duration, Instant};
+
+use serenity::{
+    client::Context,
+    framework::standard::{macros::hook, CommandResult},
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId},
+    },
+};
+
+use crate::{check_msg, controller::MusicController, settings, state};
+
+/// How long a queue paused for exceeding the session length stays paused
+/// before the bot leaves.
+const SESSION_PAUSE_SECS: u64 = 300;
+
+/// Why the bot is idle.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Idle {
+    /// The queue is empty.
+    Empty,
+    /// Nobody is listening.
+    Alone,
+    /// Nobody used a command for the maximum session length.
+    Session,
+}
+
+/// Counts every command as activity.
+#[hook]
+pub async fn after(ctx: &Context, msg: &Message, _: &str, _: CommandResult) {
+    if let Some(guild_id) = msg.guild_id {
+        activity(ctx, guild_id, msg.channel_id).await;
+    }
+}
+
+/// Records a command used in `channel_id`, which restarts the session.
+pub async fn activity(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) {
+    state::update(ctx, guild_id, |state| {
+        state.last_activity = Some(Instant::now());
+        state.text_channel = Some(channel_id);
+    })
+    .await;
+    reschedule(ctx, guild_id).await;
+}
+
+/// Pauses the queue while nobody listens to it, and resumes it when someone
+/// joins the voice channel again, given the channels a member moved between.
+pub async fn listeners_changed(ctx: &Context, guild_id: GuildId, channels: [Option<ChannelId>; 2]) {
+    let controller = MusicController::new(ctx);
+    // Members moving between other channels change nothing.
+    match controller.voice_channel(guild_id).await {
+        Ok(channel_id) if channels.contains(&Some(channel_id)) => {}
+        _ => return,
+    }
+    let listeners = match controller.listeners(guild_id).await {
+        Ok(listeners) => listeners,
+        Err(_) => return,
+    };
+    // Nor do mutes, deafens and other updates of the listeners.
+    let changed = state::update(ctx, guild_id, |state| {
+        let listeners = listeners.iter().copied().collect();
+        let changed = state.listeners != listeners;
+        state.listeners = listeners;
+        changed
+    })
+    .await;
+    if !changed {
+        return;
+    }
+    let settings = settings::get(ctx, guild_id).await;
+    let state = state::get(ctx, guild_id).await;
+    let channel_id = settings
+        .announce_channel
+        .map(ChannelId)
+        .or(state.text_channel);
+
+    let content = if listeners.is_empty() && !state.paused_alone && !settings.always_on {
+        // Songs paused on purpose stay paused once someone comes back.
+        let playing =
+            !controller.queue(guild_id).await.is_empty() && !controller.is_paused(guild_id).await;
+        if !playing || controller.pause(guild_id).await.is_err() {
+            None
+        } else {
+            state::update(ctx, guild_id, |state| state.paused_alone = true).await;
+            Some(format!(
+                "Paused since nobody is listening, leaving in {} minutes",
+                settings.alone_timeout
+            ))
+        }
+    } else if !listeners.is_empty() && state.paused_alone {
+        state::update(ctx, guild_id, |state| state.paused_alone = false).await;
+        match controller.resume(guild_id).await {
+            Ok(()) => Some("Resumed now that someone is listening".to_string()),
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+    if let (Some(channel_id), Some(content)) = (channel_id, content) {
+        check_msg(channel_id.say(&ctx.http, content).await);
+    }
+
+    // The timer depends on whether anyone listens.
+    reschedule(ctx, guild_id).await;
+}
+
+/// Replaces the timer of `guild_id`, for instance once the queue ends.
+pub async fn reschedule(ctx: &Context, guild_id: GuildId) {
+    let timer = tokio::spawn(watch(ctx.clone(), guild_id)).abort_handle();
+    let previous = state::update(ctx, guild_id, |state| state.idle_timer.replace(timer)).await;
+    if let Some(previous) = previous {
+        previous.abort();
+    }
+}
+
+fn minutes(minutes: u64) -> Duration {
+    // Settings files edited by hand are not bounded like `~config` values.
+    Duration::from_secs(minutes.saturating_mul(60))
+}
+
+/// Why the bot is idle and how much longer it may stay, or `None` if it
+/// stays until asked to leave.
+async fn situation(ctx: &Context, guild_id: GuildId) -> Option<(Duration, Idle)> {
+    let settings = settings::get(ctx, guild_id).await;
+    if settings.always_on {
+        return None;
+    }
+    let controller = MusicController::new(ctx);
+    let listeners = controller.listeners(guild_id).await.ok()?;
+    let state = state::get(ctx, guild_id).await;
+
+    if controller.queue(guild_id).await.is_empty() {
+        Some((minutes(settings.empty_timeout), Idle::Empty))
+    } else if listeners.is_empty() {
+        Some((minutes(settings.alone_timeout), Idle::Alone))
+    } else if settings.max_session > 0 {
+        let elapsed = state
+            .last_activity
+            .map(|activity| activity.elapsed())
+            .unwrap_or_default();
+        let delay = minutes(settings.max_session).saturating_sub(elapsed);
+        Some((delay, Idle::Session))
+    } else {
+        None
+    }
+}
+
+/// Waits for the bot to be idle for as long as the policy allows, then
+/// leaves the voice channel.
+async fn watch(ctx: Context, guild_id: GuildId) {
+    let idle = loop {
+        let (delay, idle) = match situation(&ctx, guild_id).await {
+            Some(situation) => situation,
+            None => return,
+        };
+        tokio::time::sleep(delay).await;
+
+        // The timer is not replaced on every change, so the bot only leaves
+        // if it is still idle for the same reason.
+        match situation(&ctx, guild_id).await {
+            Some((delay, now)) if now == idle && (idle != Idle::Session || delay.is_zero()) => {
+                break idle
+            }
+            Some(_) => continue,
+            None => return,
+        }
+    };
+
+    let settings = settings::get(&ctx, guild_id).await;
+    let state = state::get(&ctx, guild_id).await;
+    let controller = MusicController::new(&ctx);
+    let channel_id = settings
+        .announce_channel
+        .map(ChannelId)
+        .or(state.text_channel);
+    let content = match idle {
+        Idle::Empty => None,
+        Idle::Alone => Some("Left the voice channel since nobody is listening".to_string()),
+        Idle::Session => {
+            if controller.pause(guild_id).await.is_err() {
+                return;
+            }
+            let content = format!(
+                "Paused content after {} minutes. {}resume to resume",
+                settings.max_session, settings.prefix
+            );
+            if let Some(channel_id) = channel_id {
+                check_msg(channel_id.say(&ctx.http, content).await);
+            }
+
+            tokio::time::sleep(Duration::from_secs(SESSION_PAUSE_SECS)).await;
+            if !controller.is_paused(guild_id).await {
+                return;
+            }
+            None
+        }
+    };
+
+    if controller.leave(guild_id).await.is_err() {
+        return;
+    }
+    if let (Some(channel_id), Some(content)) = (channel_id, content) {
+        check_msg(channel_id.say(&ctx.http, content).await);
+    }
+}
@@ -0,0 +1,148 @@
+//! Commands invoked either by a prefixed message or by a slash command.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serenity::{
+    builder::CreateEmbed,
+    client::Context,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, UserId},
+        interactions::application_command::ApplicationCommandInteraction,
+        Permissions,
+    },
+    Result as SerenityResult,
+};
+
+enum Origin {
+    Message(Box<Message>),
+    Interaction(Box<ApplicationCommandInteraction>),
+}
+
+/// Where a command was invoked, and how to answer it.
+pub struct Invocation {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub author: UserId,
+    origin: Origin,
+    /// Whether the deferred response of an interaction was already sent, in
+    /// which case further messages are sent as followups.
+    responded: AtomicBool,
+}
+
+impl Invocation {
+    /// Wraps a message sent in a guild.
+    pub fn from_message(msg: &Message) -> Self {
+        Self {
+            guild_id: msg.guild_id.unwrap(),
+            channel_id: msg.channel_id,
+            author: msg.author.id,
+            origin: Origin::Message(Box::new(msg.clone())),
+            responded: AtomicBool::new(false),
+        }
+    }
+
+    /// Wraps a deferred interaction, or returns `None` outside of guilds.
+    pub fn from_interaction(interaction: ApplicationCommandInteraction) -> Option<Self> {
+        Some(Self {
+            guild_id: interaction.guild_id?,
+            channel_id: interaction.channel_id,
+            author: interaction.user.id,
+            origin: Origin::Interaction(Box::new(interaction)),
+            responded: AtomicBool::new(false),
+        })
+    }
+
+    /// Whether anything was sent in answer to the command yet.
+    pub fn responded(&self) -> bool {
+        self.responded.load(Ordering::SeqCst)
+    }
+
+    /// The permissions of the author in the guild, or none if they can't be
+    /// computed.
+    pub async fn permissions(&self, ctx: &Context) -> Permissions {
+        let guild = match self.guild_id.to_guild_cached(&ctx.cache).await {
+            Some(guild) => guild,
+            None => return Permissions::empty(),
+        };
+        guild
+            .member_permissions(ctx, self.author)
+            .await
+            .unwrap_or_else(|_| Permissions::empty())
+    }
+
+    pub async fn say(&self, ctx: &Context, content: impl ToString) -> SerenityResult<Message> {
+        self.send(ctx, Some(content.to_string()), None).await
+    }
+
+    /// Answers the author: messages are replied to, while interactions already
+    /// show who invoked them.
+    pub async fn reply(&self, ctx: &Context, content: impl ToString) -> SerenityResult<Message> {
+        match &self.origin {
+            Origin::Message(msg) => {
+                self.responded.store(true, Ordering::SeqCst);
+                msg.reply(ctx, content.to_string()).await
+            }
+            Origin::Interaction(_) => self.say(ctx, content).await,
+        }
+    }
+
+    pub async fn send_embed<F>(&self, ctx: &Context, f: F) -> SerenityResult<Message>
+    where
+        F: FnOnce(&mut CreateEmbed) -> &mut CreateEmbed,
+    {
+        let mut embed = CreateEmbed::default();
+        f(&mut embed);
+        self.send(ctx, None, Some(embed)).await
+    }
+
+    async fn send(
+        &self,
+        ctx: &Context,
+        content: Option<String>,
+        embed: Option<CreateEmbed>,
+    ) -> SerenityResult<Message> {
+        let followup = self.responded.swap(true, Ordering::SeqCst);
+        match &self.origin {
+            Origin::Message(msg) => {
+                msg.channel_id
+                    .send_message(&ctx.http, |m| {
+                        if let Some(content) = content {
+                            m.content(content);
+                        }
+                        if let Some(embed) = embed {
+                            m.set_embed(embed);
+                        }
+                        m
+                    })
+                    .await
+            }
+            Origin::Interaction(interaction) if followup => {
+                interaction
+                    .create_followup_message(&ctx.http, |m| {
+                        if let Some(content) = content {
+                            m.content(content);
+                        }
+                        if let Some(embed) = embed {
+                            m.add_embed(embed);
+                        }
+                        m
+                    })
+                    .await
+            }
+            Origin::Interaction(interaction) => {
+                interaction
+                    .edit_original_interaction_response(&ctx.http, |m| {
+                        if let Some(content) = content {
+                            m.content(content);
+                        }
+                        if let Some(embed) = embed {
+                            m.add_embed(embed);
+                        }
+                        m
+                    })
+                    .await
+            }
+        }
+    }
+}
@@ -1,9 +1,21 @@
-
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::{Deserialize, Serialize};
-
-use std::{collections::HashSet, env, time::Duration};
+mod autoplay;
+mod commands;
+mod controller;
+mod dj;
+mod idle;
+mod invocation;
+mod persist;
+mod playlists;
+mod resolver;
+mod session;
+mod settings;
+mod slash;
+mod spotify;
+mod state;
+mod track;
+mod youtube;
+
+use std::{collections::HashSet, env, sync::Arc};
 
 use serenity::{
     async_trait,
@@ -19,28 +31,101 @@ use serenity::{
     model::{
         channel::Message,
         gateway::Ready,
-        id::{ChannelId, GuildId, UserId},
+        id::{GuildId, UserId},
+        interactions::Interaction,
+        voice::VoiceState,
     },
+    prelude::RwLock,
     Result as SerenityResult,
 };
 
-use songbird::{
-    input::restartable::Restartable, tracks::PlayMode, Event, EventContext, SerenityInit,
-    TrackEvent,
-};
+use songbird::SerenityInit;
+
+use dj::DJ_CHECK;
+use invocation::Invocation;
+use playlists::{PlaylistStore, PLAYLIST_COMMAND};
+use resolver::SourceResolvers;
+use session::Sessions;
+use settings::{SettingsStore, CONFIG_COMMAND};
+use state::{GuildStates, LoopMode};
+use track::TrackRequest;
+
+/// Prefix of the commands, unless a guild configures its own.
+const DEFAULT_PREFIX: &str = "~";
+
+/// Maximum volume accepted by `~volume`, in percent.
+const MAX_VOLUME: u32 = 200;
 
 struct Handler;
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+
+        if let Err(why) = slash::register(&ctx).await {
+            println!("Error registering slash commands: {:?}", why);
+        }
+
+        for guild_id in persist::saved_guilds() {
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                if let Err(why) = commands::restore_saved(&ctx, guild_id).await {
+                    println!("Error restoring queue for {}: {:?}", guild_id, why);
+                }
+            });
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        slash::handle(&ctx, interaction).await;
+    }
+
+    async fn voice_state_update(
+        &self,
+        ctx: Context,
+        guild_id: Option<GuildId>,
+        old: Option<VoiceState>,
+        new: VoiceState,
+    ) {
+        if let Some(guild_id) = guild_id {
+            let channels = [old.and_then(|old| old.channel_id), new.channel_id];
+            idle::listeners_changed(&ctx, guild_id, channels).await;
+        }
     }
 }
 
 #[group]
+#[checks(DJ)]
 #[commands(
-    deafen, mute, queue, skip, stop, undeafen, unmute, join, pause, resume, shuffle, play
+    deafen,
+    mute,
+    queue,
+    skip,
+    stop,
+    undeafen,
+    unmute,
+    join,
+    leave,
+    pause,
+    resume,
+    shuffle,
+    play,
+    search,
+    restore,
+    playlist,
+    config,
+    nowplaying,
+    list,
+    remove,
+    move_track,
+    skipto,
+    removedupes,
+    loop_mode,
+    volume,
+    seek,
+    ff,
+    rw
 )]
 struct General;
 
@@ -49,16 +134,42 @@ async fn main() {
     dotenv::dotenv().ok();
     // Configure the client with your Discord bot token in the environment.
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    // Slash commands are registered for the application of the bot.
+    let application_id = env::var("APPLICATION_ID")
+        .expect("Expected an application id in the environment")
+        .parse::<u64>()
+        .expect("Application id is not a valid id");
 
     let framework = StandardFramework::new()
-        .configure(|c| c.prefix("~"))
+        .configure(|c| {
+            // The static prefix would keep working after a guild changes it.
+            c.prefix("").dynamic_prefix(|ctx, msg| {
+                Box::pin(async move {
+                    Some(match msg.guild_id {
+                        Some(guild_id) => settings::get(ctx, guild_id).await.prefix,
+                        None => DEFAULT_PREFIX.to_string(),
+                    })
+                })
+            })
+        })
+        .on_dispatch_error(dj::dispatch_error)
+        .after(idle::after)
         .help(&MY_HELP)
         .group(&GENERAL_GROUP);
 
+    let playlists = PlaylistStore::load().expect("Err loading playlists");
+    let settings = SettingsStore::load().expect("Err loading settings");
+
     let mut client = Client::builder(&token)
+        .application_id(application_id)
         .event_handler(Handler)
         .framework(framework)
         .register_songbird()
+        .type_map_insert::<PlaylistStore>(Arc::new(RwLock::new(playlists)))
+        .type_map_insert::<SettingsStore>(Arc::new(RwLock::new(settings)))
+        .type_map_insert::<GuildStates>(Default::default())
+        .type_map_insert::<SourceResolvers>(Default::default())
+        .type_map_insert::<Sessions>(Default::default())
         .await
         .expect("Err creating client");
 
@@ -69,41 +180,9 @@ async fn main() {
 }
 
 #[command]
+#[only_in(guilds)]
 async fn deafen(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    let handler_lock = match manager.get(guild_id) {
-        Some(handler) => handler,
-        None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
-
-            return Ok(());
-        }
-    };
-
-    let mut handler = handler_lock.lock().await;
-
-    if handler.is_deaf() {
-        check_msg(msg.channel_id.say(&ctx.http, "Already deafened").await);
-    } else {
-        if let Err(e) = handler.deafen(true).await {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
-                    .await,
-            );
-        }
-
-        check_msg(msg.channel_id.say(&ctx.http, "Deafened").await);
-    }
-
-    Ok(())
+    commands::deafen(ctx, &Invocation::from_message(msg)).await
 }
 
 #[help]
@@ -123,545 +202,242 @@ async fn my_help(
     let _ = help_commands::with_embeds(context, msg, args, help_options, groups, owners).await;
     Ok(())
 }
-
 #[command]
 #[only_in(guilds)]
 async fn join(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let channel_id = guild
-        .voice_states
-        .get(&msg.author.id)
-        .and_then(|voice_state| voice_state.channel_id);
-
-    let connect_to = match channel_id {
-        Some(channel) => channel,
-        None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
-
-            return Ok(());
-        }
-    };
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    let (_, _) = manager.join(guild_id, connect_to).await;
-
-    Ok(())
+    commands::join(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
+    commands::leave(ctx, &Invocation::from_message(msg)).await
+}
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-    let has_handler = manager.get(guild_id).is_some();
-
-    if has_handler {
-        if let Err(e) = manager.remove(guild_id).await {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
-                    .await,
-            );
-        }
+#[command]
+#[only_in(guilds)]
+async fn mute(ctx: &Context, msg: &Message) -> CommandResult {
+    commands::mute(ctx, &Invocation::from_message(msg)).await
+}
 
-        check_msg(msg.channel_id.say(&ctx.http, "Left voice channel").await);
-    } else {
-        check_msg(msg.reply(ctx, "Not in a voice channel").await);
+#[command]
+#[only_in(guilds)]
+async fn search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let terms = args.raw_quoted().collect::<Vec<&str>>().join(" ");
+    if terms.is_empty() {
+        usage(ctx, msg, "search <terms>").await;
+
+        return Ok(());
     }
+    commands::search(ctx, &Invocation::from_message(msg), terms).await
+}
 
-    Ok(())
+#[command]
+#[only_in(guilds)]
+async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let query = args.raw_quoted().collect::<Vec<&str>>().join(" ");
+    commands::play(ctx, &Invocation::from_message(msg), query).await
 }
 
 #[command]
 #[only_in(guilds)]
-async fn mute(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
+async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let query = args.raw_quoted().collect::<Vec<&str>>().join(" ");
+    commands::queue(ctx, &Invocation::from_message(msg), query).await
+}
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+#[command]
+#[only_in(guilds)]
+async fn restore(ctx: &Context, msg: &Message) -> CommandResult {
+    commands::restore(ctx, &Invocation::from_message(msg)).await
+}
 
-    let handler_lock = match manager.get(guild_id) {
-        Some(handler) => handler,
-        None => {
-            check_msg(msg.reply(ctx, "Not in a voice channel").await);
+#[command]
+#[only_in(guilds)]
+async fn skip(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    commands::skip(ctx, &Invocation::from_message(msg)).await
+}
 
-            return Ok(());
+#[command]
+#[only_in(guilds)]
+async fn remove(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match track::parse_range(args.rest()) {
+        Some((start, end)) => {
+            commands::remove(ctx, &Invocation::from_message(msg), start, end).await
         }
-    };
+        None => {
+            usage(ctx, msg, "remove <position> or <start>-<end>").await;
 
-    let mut handler = handler_lock.lock().await;
+            Ok(())
+        }
+    }
+}
 
-    if handler.is_mute() {
-        check_msg(msg.channel_id.say(&ctx.http, "Already muted").await);
-    } else {
-        if let Err(e) = handler.mute(true).await {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
-                    .await,
-            );
+#[command("move")]
+#[only_in(guilds)]
+async fn move_track(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match (args.single::<usize>(), args.single::<usize>()) {
+        (Ok(from), Ok(to)) => {
+            commands::move_track(ctx, &Invocation::from_message(msg), from, to).await
         }
+        _ => {
+            usage(ctx, msg, "move <from> <to>").await;
 
-        check_msg(msg.channel_id.say(&ctx.http, "Now muted").await);
+            Ok(())
+        }
     }
-
-    Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
-async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    stop(ctx, msg, args.clone()).await?;
-    queue(ctx, msg, args).await?;
-    Ok(())
+async fn skipto(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match args.single::<usize>() {
+        Ok(index) => commands::skipto(ctx, &Invocation::from_message(msg), index).await,
+        Err(_) => {
+            usage(ctx, msg, "skipto <position>").await;
+
+            Ok(())
+        }
+    }
 }
+
 #[command]
 #[only_in(guilds)]
-async fn queue(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let url = args.raw_quoted().collect::<Vec<&str>>().join(" ");
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    let handler_lock = if let Some(handler_lock) = manager.get(guild_id) {
-        handler_lock
-    } else {
-        let guild = msg.guild(&ctx.cache).await.unwrap();
-        let guild_id = guild.id;
-
-        let channel_id = guild
-            .voice_states
-            .get(&msg.author.id)
-            .and_then(|voice_state| voice_state.channel_id);
+async fn removedupes(ctx: &Context, msg: &Message) -> CommandResult {
+    commands::removedupes(ctx, &Invocation::from_message(msg)).await
+}
 
-        let connect_to = match channel_id {
-            Some(channel) => channel,
+#[command("loop")]
+#[only_in(guilds)]
+async fn loop_mode(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let mode = match args.rest().trim() {
+        "" => None,
+        mode => match LoopMode::parse(mode) {
+            Some(mode) => Some(mode),
             None => {
-                check_msg(msg.reply(ctx, "Not in a voice channel").await);
+                usage(ctx, msg, "loop track|queue|off").await;
 
                 return Ok(());
             }
-        };
-
-        let manager = songbird::get(ctx)
-            .await
-            .expect("Songbird Voice client placed in at initialisation.")
-            .clone();
-
-        let (handle_lock, _) = manager.join(guild_id, connect_to).await;
-        handle_lock
+        },
     };
 
-    let mut handler = handler_lock.lock().await;
-
-    // Here, we use lazy restartable sources to make sure that we don't pay
-    // for decoding, playback on tracks which aren't actually live yet.
-    let sources = if !url.starts_with("http") {
-        match Restartable::ytdl_search(url, true).await {
-            Ok(source) => vec![source],
-            Err(why) => {
-                println!("Err starting source: {:?}", why);
-
-                check_msg(msg.channel_id.say(&ctx.http, "Error sourcing ffmpeg").await);
+    commands::loop_mode(ctx, &Invocation::from_message(msg), mode).await
+}
 
-                return Ok(());
-            }
-        }
-    } else if url.starts_with("https://www.youtube.com/playlist?list=") {
-        let mut sources = Vec::new();
-        let client = reqwest::Client::builder()
-            .user_agent("User agent: timothee.leberre@gmail.com")
-            .build()
-            .unwrap();
-        let playlist_id = url
-            .strip_prefix("https://www.youtube.com/playlist?list=")
-            .unwrap();
-        let url = format!("https://www.googleapis.com/youtube/v3/playlistItems?part=snippet&maxResults=100&playlistId={}&key={}", playlist_id, env::var("GOOGLE_TOKEN").expect("Expected a token in the environment"));
-        let resp = client.get(url).send().await?.json::<Playlist>().await?;
-        for item in resp.items {
-            let url = format!(
-                "https://www.youtube.com/watch?v={}",
-                item.snippet.resourceId.videoId
-            );
-            match Restartable::ytdl(url, true).await {
-                Ok(source) => sources.push(source),
-                Err(why) => {
-                    println!("Err starting source: {:?}", why);
-                }
-            }
-        }
-        sources
+#[command]
+#[only_in(guilds)]
+async fn volume(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let volume = if args.is_empty() {
+        None
     } else {
-        match Restartable::ytdl(url, true).await {
-            Ok(source) => vec![source],
-            Err(why) => {
-                println!("Err starting source: {:?}", why);
-
-                check_msg(msg.channel_id.say(&ctx.http, "Error sourcing ffmpeg").await);
+        match args.single::<u32>() {
+            Ok(volume) => Some(volume),
+            Err(_) => {
+                check_msg(
+                    msg.channel_id
+                        .say(
+                            &ctx.http,
+                            format!("Expected a volume between 0 and {}", MAX_VOLUME),
+                        )
+                        .await,
+                );
 
                 return Ok(());
             }
         }
     };
-    let n = sources.len();
-    for source in sources {
-        handler.enqueue_source(source.into());
-    }
-
-    let guild_id = msg.guild_id.unwrap();
-    let chan_id = msg.channel_id;
-
-    handler.add_global_event(
-        Event::Track(TrackEvent::End),
-        TrackEndNotifier {
-            guild_id,
-            ctx: ctx.clone(),
-        },
-    );
-
-    handler.add_global_event(
-        Event::Delayed(Duration::from_secs(7200)),
-        DurationElapsedNotifier {
-            guild_id,
-            chan_id,
-            quit: false,
-            ctx: ctx.clone(),
-        },
-    );
-
-    check_msg(
-        msg.channel_id
-            .say(
-                &ctx.http,
-                format!(
-                    "Added {} songs to queue: total length is {}",
-                    n,
-                    handler.queue().len()
-                ),
-            )
-            .await,
-    );
 
-    Ok(())
-}
-
-struct TrackEndNotifier {
-    guild_id: GuildId,
-    ctx: Context,
+    commands::volume(ctx, &Invocation::from_message(msg), volume).await
 }
 
-#[async_trait]
-impl songbird::EventHandler for TrackEndNotifier {
-    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
-        let manager = songbird::get(&self.ctx)
-            .await
-            .expect("Songbird Voice client placed in at initialisation.")
-            .clone();
-        if if let Some(handler_lock) = manager.get(self.guild_id) {
-            let handler = handler_lock.lock().await;
-            let queue = handler.queue();
-
-            if queue.is_empty() {
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        } {
-            manager.remove(self.guild_id).await.unwrap();
-        };
+#[command]
+#[only_in(guilds)]
+async fn seek(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    match track::parse_timestamp(args.rest()) {
+        Some(position) => commands::seek(ctx, &Invocation::from_message(msg), position).await,
+        None => {
+            usage(ctx, msg, "seek <mm:ss>").await;
 
-        None
+            Ok(())
+        }
     }
 }
 
-struct DurationElapsedNotifier {
-    guild_id: GuildId,
-    chan_id: ChannelId,
-    quit: bool,
-    ctx: Context,
-}
+#[command]
+#[only_in(guilds)]
+async fn ff(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match args.single::<u64>() {
+        Ok(secs) => commands::ff(ctx, &Invocation::from_message(msg), secs).await,
+        Err(_) => {
+            usage(ctx, msg, "ff <seconds>").await;
 
-#[async_trait]
-impl songbird::EventHandler for DurationElapsedNotifier {
-    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
-        if !self.quit {
-            let manager = songbird::get(&self.ctx)
-                .await
-                .expect("Songbird Voice client placed in at initialisation.")
-                .clone();
-            if let Some(handler_lock) = manager.get(self.guild_id) {
-                let mut handler = handler_lock.lock().await;
-                handler.add_global_event(
-                    Event::Delayed(Duration::from_secs(300)),
-                    DurationElapsedNotifier {
-                        guild_id: self.guild_id,
-                        chan_id: self.chan_id,
-                        quit: true,
-                        ctx: self.ctx.clone(),
-                    },
-                );
-                let queue = handler.queue();
-                let _ = queue.pause();
-            };
-            self.chan_id
-                .say(&self.ctx, "Paused content after 2 hours. ~resume to resume")
-                .await
-                .unwrap();
-        } else {
-            let manager = songbird::get(&self.ctx)
-                .await
-                .expect("Songbird Voice client placed in at initialisation.")
-                .clone();
-            if if let Some(handler_lock) = manager.get(self.guild_id) {
-                let handler = handler_lock.lock().await;
-                let queue = handler.queue();
-                if queue.current().unwrap().get_info().await.unwrap().playing == PlayMode::Pause {
-                    true
-                } else {
-                    false
-                }
-            } else {
-                false
-            } {
-                let _ = manager.remove(self.guild_id).await;
-            }
+            Ok(())
         }
-
-        None
     }
 }
 
 #[command]
 #[only_in(guilds)]
-async fn skip(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
+async fn rw(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match args.single::<u64>() {
+        Ok(secs) => commands::rw(ctx, &Invocation::from_message(msg), secs).await,
+        Err(_) => {
+            usage(ctx, msg, "rw <seconds>").await;
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.skip();
-
-        check_msg(
-            msg.channel_id
-                .say(
-                    &ctx.http,
-                    format!("Song skipped: {} in queue.", queue.len()),
-                )
-                .await,
-        );
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
+            Ok(())
+        }
     }
-
-    Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
 async fn shuffle(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.pause();
-        let _ = queue.modify_queue(|q| q.make_contiguous().shuffle(&mut thread_rng()));
-        let _ = queue.resume();
-        check_msg(msg.channel_id.say(&ctx.http, "Shuffled the queue").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
-    }
-
-    Ok(())
+    commands::shuffle(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn pause(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.pause();
-
-        check_msg(msg.channel_id.say(&ctx.http, "Paused the queue").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
-    }
-
-    Ok(())
+    commands::pause(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn resume(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.resume();
-
-        check_msg(msg.channel_id.say(&ctx.http, "Resumed the queue").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
-    }
-
-    Ok(())
+    commands::resume(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn stop(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let handler = handler_lock.lock().await;
-        let queue = handler.queue();
-        let _ = queue.stop();
-
-        check_msg(msg.channel_id.say(&ctx.http, "Queue cleared.").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to play in")
-                .await,
-        );
-    }
-
-    Ok(())
+    commands::stop(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn undeafen(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        if let Err(e) = handler.deafen(false).await {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
-                    .await,
-            );
-        }
-
-        check_msg(msg.channel_id.say(&ctx.http, "Undeafened").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to undeafen in")
-                .await,
-        );
-    }
-
-    Ok(())
+    commands::undeafen(ctx, &Invocation::from_message(msg)).await
 }
 
 #[command]
 #[only_in(guilds)]
 async fn unmute(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).await.unwrap();
-    let guild_id = guild.id;
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    if let Some(handler_lock) = manager.get(guild_id) {
-        let mut handler = handler_lock.lock().await;
-        if let Err(e) = handler.mute(false).await {
-            check_msg(
-                msg.channel_id
-                    .say(&ctx.http, format!("Failed: {:?}", e))
-                    .await,
-            );
-        }
+    commands::unmute(ctx, &Invocation::from_message(msg)).await
+}
 
-        check_msg(msg.channel_id.say(&ctx.http, "Unmuted").await);
-    } else {
-        check_msg(
-            msg.channel_id
-                .say(&ctx.http, "Not in a voice channel to unmute in")
-                .await,
-        );
-    }
+#[command]
+#[only_in(guilds)]
+#[aliases("np")]
+async fn nowplaying(ctx: &Context, msg: &Message) -> CommandResult {
+    commands::nowplaying(ctx, &Invocation::from_message(msg)).await
+}
 
-    Ok(())
+#[command]
+#[only_in(guilds)]
+async fn list(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let page = args.single::<usize>().ok();
+    commands::list(ctx, &Invocation::from_message(msg), page).await
 }
 
 /// Checks that a message successfully sent; if not, then logs why to stdout.
@@ -671,31 +447,13 @@ fn check_msg(result: SerenityResult<Message>) {
     }
 }
 
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
-#[serde(default)]
-#[allow(non_snake_case)]
-pub struct Playlist {
-    //pub nextPageToken: String,
-    pub items: Vec<Item>,
-}
-
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
-#[serde(default)]
-#[allow(non_snake_case)]
-pub struct Item {
-    pub snippet: Snippet,
-}
-
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
-#[serde(default)]
-#[allow(non_snake_case)]
-pub struct Snippet {
-    pub resourceId: RessourceId,
-}
-
-#[derive(Default, Serialize, Deserialize, Clone, Debug)]
-#[serde(default)]
-#[allow(non_snake_case)]
-pub struct RessourceId {
-    pub videoId: String,
+/// Tells how to use a command, given its usage without the prefix of the
+/// guild.
+async fn usage(ctx: &Context, msg: &Message, usage: &str) {
+    let prefix = match msg.guild_id {
+        Some(guild_id) => settings::get(ctx, guild_id).await.prefix,
+        None => DEFAULT_PREFIX.to_string(),
+    };
+    let content = format!("Usage: {}{}", prefix, usage);
+    check_msg(msg.channel_id.say(&ctx.http, content).await);
 }
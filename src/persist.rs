@@ -0,0 +1,130 @@
+//! On-disk snapshots of each guild's queue, so that it survives restarts.
+
+use std::{env, fs, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{ChannelId, GuildId};
+use songbird::Call;
+
+use crate::track;
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct QueueSnapshot {
+    pub voice_channel: u64,
+    pub text_channel: u64,
+    /// Position in the track at the head of the queue.
+    pub position: Duration,
+    pub tracks: Vec<SavedTrack>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SavedTrack {
+    pub url: String,
+    pub requester: u64,
+    /// Channel of the request, missing from older snapshots.
+    pub channel: Option<u64>,
+}
+
+/// Directory holding the bot's data, read from `KOBOT_DATA` (defaults to `data`).
+pub fn data_dir() -> PathBuf {
+    env::var("KOBOT_DATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("data"))
+}
+
+fn queues_dir() -> PathBuf {
+    data_dir().join("queues")
+}
+
+fn queue_path(guild_id: GuildId) -> PathBuf {
+    queues_dir().join(format!("{}.ron", guild_id.0))
+}
+
+/// Builds a snapshot of the call's current queue.
+pub async fn snapshot(handler: &Call, text_channel: ChannelId) -> QueueSnapshot {
+    let queue = handler.queue().current_queue();
+    let position = match queue.first() {
+        Some(current) => current
+            .get_info()
+            .await
+            .map(|info| info.position)
+            .unwrap_or_default(),
+        None => Duration::default(),
+    };
+
+    let mut tracks = Vec::with_capacity(queue.len());
+    for handle in &queue {
+        if let Some(url) = track::source_url(handle).await {
+            let request = track::request_of(handle).await;
+            tracks.push(SavedTrack {
+                url,
+                requester: request.as_ref().map(|r| r.requester.0).unwrap_or_default(),
+                channel: request.map(|r| r.channel.0),
+            });
+        }
+    }
+
+    QueueSnapshot {
+        voice_channel: handler.current_channel().map(|c| c.0).unwrap_or_default(),
+        text_channel: text_channel.0,
+        position,
+        tracks,
+    }
+}
+
+pub fn save(guild_id: GuildId, snapshot: &QueueSnapshot) -> anyhow::Result<()> {
+    fs::create_dir_all(queues_dir())?;
+    let content = ron::ser::to_string_pretty(snapshot, Default::default())?;
+    fs::write(queue_path(guild_id), content)?;
+    Ok(())
+}
+
+pub fn load(guild_id: GuildId) -> anyhow::Result<Option<QueueSnapshot>> {
+    let path = queue_path(guild_id);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(Some(ron::de::from_str(&content)?))
+}
+
+pub fn clear(guild_id: GuildId) {
+    let path = queue_path(guild_id);
+    if path.exists() {
+        if let Err(why) = fs::remove_file(path) {
+            println!("Error removing queue snapshot: {:?}", why);
+        }
+    }
+}
+
+/// Guilds which have a saved queue.
+pub fn saved_guilds() -> Vec<GuildId> {
+    let entries = match fs::read_dir(queues_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "ron" {
+                return None;
+            }
+            path.file_stem()?.to_str()?.parse().ok().map(GuildId)
+        })
+        .collect()
+}
+
+/// Saves the call's queue, or clears the snapshot if the queue is empty.
+pub async fn save_call(guild_id: GuildId, handler: &Call, text_channel: ChannelId) {
+    if handler.queue().is_empty() {
+        clear(guild_id);
+        return;
+    }
+    let snapshot = snapshot(handler, text_channel).await;
+    if let Err(why) = save(guild_id, &snapshot) {
+        println!("Error saving queue: {:?}", why);
+    }
+}
@@ -0,0 +1,305 @@
+//! Named playlists, saved per guild or per user.
+
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::{
+        channel::Message,
+        id::{GuildId, UserId},
+    },
+    prelude::{RwLock, TypeMapKey},
+};
+
+use crate::{
+    check_msg, commands, controller::MusicController, invocation::Invocation, persist, track,
+};
+
+/// Who a playlist belongs to.
+#[derive(Clone, Copy, Debug)]
+pub enum Owner {
+    Guild(GuildId),
+    User(UserId),
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PlaylistStore {
+    pub guilds: BTreeMap<u64, BTreeMap<String, Vec<String>>>,
+    pub users: BTreeMap<u64, BTreeMap<String, Vec<String>>>,
+}
+
+impl TypeMapKey for PlaylistStore {
+    type Value = Arc<RwLock<PlaylistStore>>;
+}
+
+impl PlaylistStore {
+    fn path() -> PathBuf {
+        persist::data_dir().join("playlists.ron")
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(ron::de::from_str(&content)?)
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        fs::create_dir_all(persist::data_dir())?;
+        let content = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(Self::path(), content)?;
+        Ok(())
+    }
+
+    pub fn playlists(&self, owner: Owner) -> Option<&BTreeMap<String, Vec<String>>> {
+        match owner {
+            Owner::Guild(id) => self.guilds.get(&id.0),
+            Owner::User(id) => self.users.get(&id.0),
+        }
+    }
+
+    pub fn playlists_mut(&mut self, owner: Owner) -> &mut BTreeMap<String, Vec<String>> {
+        match owner {
+            Owner::Guild(id) => self.guilds.entry(id.0).or_default(),
+            Owner::User(id) => self.users.entry(id.0).or_default(),
+        }
+    }
+
+    pub fn get(&self, owner: Owner, name: &str) -> Option<&Vec<String>> {
+        self.playlists(owner)?.get(name)
+    }
+}
+
+/// Reads the owner of the playlist from the arguments: playlists prefixed by
+/// `me` belong to the author, the others to the guild.
+fn parse_owner(msg: &Message, args: &mut Args) -> Owner {
+    if args.current() == Some("me") {
+        args.advance();
+        Owner::User(msg.author.id)
+    } else {
+        Owner::Guild(msg.guild_id.unwrap())
+    }
+}
+
+/// Reads the name of a playlist, quoted if it contains spaces.
+fn parse_name(args: &mut Args) -> Option<String> {
+    args.single_quoted::<String>()
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// Tells how to use a playlist subcommand.
+async fn usage(ctx: &Context, msg: &Message, usage: &str) -> CommandResult {
+    let usage = format!("{}, quoting names with spaces", usage);
+    crate::usage(ctx, msg, &usage).await;
+
+    Ok(())
+}
+
+async fn store(ctx: &Context) -> Arc<RwLock<PlaylistStore>> {
+    ctx.data
+        .read()
+        .await
+        .get::<PlaylistStore>()
+        .expect("Playlist store placed in at initialisation.")
+        .clone()
+}
+
+fn save_store(store: &PlaylistStore) {
+    if let Err(why) = store.save() {
+        println!("Error saving playlists: {:?}", why);
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(
+    playlist_save,
+    playlist_load,
+    playlist_list,
+    playlist_delete,
+    playlist_add
+)]
+async fn playlist(ctx: &Context, msg: &Message) -> CommandResult {
+    usage(ctx, msg, "playlist save|load|list|delete|add [me] <name>").await
+}
+
+#[command("save")]
+#[only_in(guilds)]
+async fn playlist_save(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let owner = parse_owner(msg, &mut args);
+    match parse_name(&mut args) {
+        Some(name) if args.is_empty() => {
+            save(ctx, &Invocation::from_message(msg), owner, name).await
+        }
+        _ => usage(ctx, msg, "playlist save [me] <name>").await,
+    }
+}
+
+#[command("load")]
+#[only_in(guilds)]
+async fn playlist_load(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let owner = parse_owner(msg, &mut args);
+    match parse_name(&mut args) {
+        Some(name) if args.is_empty() => {
+            load(ctx, &Invocation::from_message(msg), owner, &name).await
+        }
+        _ => usage(ctx, msg, "playlist load [me] <name>").await,
+    }
+}
+
+#[command("list")]
+#[only_in(guilds)]
+async fn playlist_list(ctx: &Context, msg: &Message) -> CommandResult {
+    list(ctx, &Invocation::from_message(msg)).await
+}
+
+#[command("delete")]
+#[only_in(guilds)]
+async fn playlist_delete(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let owner = parse_owner(msg, &mut args);
+    match parse_name(&mut args) {
+        Some(name) if args.is_empty() => {
+            delete(ctx, &Invocation::from_message(msg), owner, &name).await
+        }
+        _ => usage(ctx, msg, "playlist delete [me] <name>").await,
+    }
+}
+
+#[command("add")]
+#[only_in(guilds)]
+async fn playlist_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let owner = parse_owner(msg, &mut args);
+    match (parse_name(&mut args), args.single::<String>()) {
+        (Some(name), Ok(url)) if args.is_empty() => {
+            add(ctx, &Invocation::from_message(msg), owner, name, url).await
+        }
+        _ => usage(ctx, msg, "playlist add [me] <name> <url>").await,
+    }
+}
+
+/// Names of the playlists of `owner`, for autocompletion.
+pub async fn names(ctx: &Context, owner: Owner) -> Vec<String> {
+    let store = store(ctx).await;
+    let store = store.read().await;
+    store
+        .playlists(owner)
+        .map(|playlists| playlists.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Saves the current queue as playlist `name`.
+pub async fn save(ctx: &Context, inv: &Invocation, owner: Owner, name: String) -> CommandResult {
+    let mut urls = Vec::new();
+    for handle in MusicController::new(ctx).queue(inv.guild_id).await {
+        if let Some(url) = track::source_url(&handle).await {
+            urls.push(url);
+        }
+    }
+
+    if urls.is_empty() {
+        check_msg(inv.say(ctx, "The queue is empty").await);
+
+        return Ok(());
+    }
+
+    let n = urls.len();
+    let store = store(ctx).await;
+    let mut store = store.write().await;
+    store.playlists_mut(owner).insert(name.clone(), urls);
+    save_store(&store);
+    // Other playlist commands don't wait for the reply.
+    drop(store);
+
+    check_msg(
+        inv.say(ctx, format!("Saved {} songs to playlist `{}`", n, name))
+            .await,
+    );
+
+    Ok(())
+}
+
+/// Adds the songs of playlist `name` to the queue.
+pub async fn load(ctx: &Context, inv: &Invocation, owner: Owner, name: &str) -> CommandResult {
+    let urls = store(ctx).await.read().await.get(owner, name).cloned();
+    match urls {
+        Some(urls) => commands::enqueue_queries(ctx, inv, urls).await?,
+        None => check_msg(inv.say(ctx, format!("No playlist named `{}`", name)).await),
+    }
+
+    Ok(())
+}
+
+/// Lists the playlists of the guild and of the author.
+pub async fn list(ctx: &Context, inv: &Invocation) -> CommandResult {
+    let store = store(ctx).await;
+    let store = store.read().await;
+
+    let describe = |owner| {
+        store
+            .playlists(owner)
+            .filter(|playlists| !playlists.is_empty())
+            .map(|playlists| {
+                playlists
+                    .iter()
+                    .map(|(name, urls)| format!("`{}` ({} songs)", name, urls.len()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_else(|| "none".to_string())
+    };
+
+    let content = format!(
+        "Server playlists: {}\nYour playlists: {}",
+        describe(Owner::Guild(inv.guild_id)),
+        describe(Owner::User(inv.author)),
+    );
+    drop(store);
+    check_msg(inv.say(ctx, content).await);
+
+    Ok(())
+}
+
+pub async fn delete(ctx: &Context, inv: &Invocation, owner: Owner, name: &str) -> CommandResult {
+    let store = store(ctx).await;
+    let mut store = store.write().await;
+    let content = if store.playlists_mut(owner).remove(name).is_some() {
+        save_store(&store);
+        format!("Deleted playlist `{}`", name)
+    } else {
+        format!("No playlist named `{}`", name)
+    };
+    drop(store);
+    check_msg(inv.say(ctx, content).await);
+
+    Ok(())
+}
+
+/// Appends `url` to playlist `name`, creating it if needed.
+pub async fn add(
+    ctx: &Context,
+    inv: &Invocation,
+    owner: Owner,
+    name: String,
+    url: String,
+) -> CommandResult {
+    let store = store(ctx).await;
+    let mut store = store.write().await;
+    let urls = store.playlists_mut(owner).entry(name.clone()).or_default();
+    urls.push(url);
+    let n = urls.len();
+    save_store(&store);
+    drop(store);
+
+    check_msg(
+        inv.say(ctx, format!("Added to playlist `{}`: {} songs", name, n))
+            .await,
+    );
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+//! Turning queries into playable sources.
+//!
+//! Each kind of query is handled by a [`SourceResolver`]; the registry tries
+//! them in order and uses the first one which accepts the query.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context as _};
+use reqwest::Url;
+use serenity::{async_trait, client::Context, prelude::TypeMapKey};
+use songbird::input::restartable::Restartable;
+
+use crate::{
+    spotify,
+    youtube::{self, PlaylistBackend},
+};
+
+/// Extensions of the audio files which are played directly through ffmpeg.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "opus", "flac", "wav", "m4a", "aac", "webm"];
+
+/// Prefix of queries for files of the local music directory.
+const LOCAL_PREFIX: &str = "file:";
+
+/// Default cap on the number of items imported from a single playlist.
+const DEFAULT_PLAYLIST_MAX_ITEMS: usize = 500;
+
+/// A song found for a query.
+pub enum Song {
+    /// A playable source, paired with the URL or query it can be recreated
+    /// from.
+    Ready(Restartable, String),
+    /// The URL or query of a song which is only resolved when enqueued, so
+    /// that large playlists don't delay playback.
+    Pending(String),
+}
+
+/// Shows how a long resolution, such as a playlist import, is going.
+#[async_trait]
+pub trait Progress: Send + Sync {
+    async fn report(&self, status: String);
+}
+
+/// Ignores progress, for resolutions nobody is waiting on.
+pub struct NoProgress;
+
+#[async_trait]
+impl Progress for NoProgress {
+    async fn report(&self, _: String) {}
+}
+
+#[async_trait]
+pub trait SourceResolver: Send + Sync {
+    /// Whether this resolver handles `query`.
+    fn accepts(&self, query: &str) -> bool;
+
+    /// Finds the songs for `query`.
+    async fn resolve(&self, query: &str, progress: &dyn Progress) -> anyhow::Result<Vec<Song>>;
+}
+
+/// The resolvers, in the order they are tried.
+pub struct SourceResolvers {
+    resolvers: Vec<Box<dyn SourceResolver>>,
+}
+
+impl TypeMapKey for SourceResolvers {
+    type Value = Arc<SourceResolvers>;
+}
+
+impl Default for SourceResolvers {
+    fn default() -> Self {
+        let mut resolvers: Vec<Box<dyn SourceResolver>> = vec![
+            Box::new(Spotify),
+            Box::new(YoutubePlaylist {
+                backend: PlaylistBackend::from_env(),
+            }),
+            Box::new(YoutubeVideo),
+            Box::new(DirectFile),
+        ];
+        if let Ok(root) = env::var("KOBOT_MUSIC_DIR") {
+            resolvers.push(Box::new(LocalFile {
+                root: PathBuf::from(root),
+            }));
+        }
+        resolvers.push(Box::new(Ytdl));
+        resolvers.push(Box::new(Search));
+        Self::new(resolvers)
+    }
+}
+
+impl SourceResolvers {
+    pub fn new(resolvers: Vec<Box<dyn SourceResolver>>) -> Self {
+        Self { resolvers }
+    }
+
+    /// Finds the songs for `query` with the first resolver accepting it.
+    pub async fn resolve(&self, query: &str, progress: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        match self.resolvers.iter().find(|r| r.accepts(query)) {
+            Some(resolver) => resolver.resolve(query, progress).await,
+            None => bail!("Unsupported query: {}", query),
+        }
+    }
+
+    /// Builds the source of a single song, such as a saved track.
+    pub async fn resolve_one(&self, query: &str) -> anyhow::Result<Restartable> {
+        match self.resolve(query, &NoProgress).await?.into_iter().next() {
+            Some(Song::Ready(source, _)) => Ok(source),
+            _ => bail!("Nothing found for {}", query),
+        }
+    }
+}
+
+pub async fn get(ctx: &Context) -> Arc<SourceResolvers> {
+    ctx.data
+        .read()
+        .await
+        .get::<SourceResolvers>()
+        .expect("Source resolvers placed in at initialisation.")
+        .clone()
+}
+
+/// Maximum number of playlist items to import, read from `PLAYLIST_MAX_ITEMS`.
+fn playlist_max_items() -> usize {
+    env::var("PLAYLIST_MAX_ITEMS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(DEFAULT_PLAYLIST_MAX_ITEMS)
+}
+
+/// Terms to search for on YouTube, playing the first result.
+pub struct Search;
+
+#[async_trait]
+impl SourceResolver for Search {
+    fn accepts(&self, query: &str) -> bool {
+        !query.starts_with("http")
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let source = Restartable::ytdl_search(query.to_string(), true)
+            .await
+            .context("Error sourcing ffmpeg")?;
+        Ok(vec![Song::Ready(source, query.to_string())])
+    }
+}
+
+/// Links to songs on sites supported by youtube-dl, such as SoundCloud or
+/// Bandcamp.
+pub struct Ytdl;
+
+#[async_trait]
+impl SourceResolver for Ytdl {
+    fn accepts(&self, query: &str) -> bool {
+        query.starts_with("http")
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let source = Restartable::ytdl(query.to_string(), true)
+            .await
+            .context("Error sourcing ffmpeg")?;
+        Ok(vec![Song::Ready(source, query.to_string())])
+    }
+}
+
+/// Links to audio files, played without youtube-dl.
+pub struct DirectFile;
+
+#[async_trait]
+impl SourceResolver for DirectFile {
+    fn accepts(&self, query: &str) -> bool {
+        let url = match Url::parse(query) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+            _ => return false,
+        };
+        match Path::new(url.path()).extension() {
+            Some(extension) => {
+                let extension = extension.to_string_lossy().to_lowercase();
+                AUDIO_EXTENSIONS.contains(&extension.as_str())
+            }
+            None => false,
+        }
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let source = Restartable::ffmpeg(query.to_string(), true)
+            .await
+            .context("Error sourcing ffmpeg")?;
+        Ok(vec![Song::Ready(source, query.to_string())])
+    }
+}
+
+/// Files of the local music directory, given as `file:<path>`.
+pub struct LocalFile {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl SourceResolver for LocalFile {
+    fn accepts(&self, query: &str) -> bool {
+        query.starts_with(LOCAL_PREFIX)
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let name = query[LOCAL_PREFIX.len()..].trim();
+        let root = self.root.canonicalize()?;
+        let path = root
+            .join(name)
+            .canonicalize()
+            .with_context(|| format!("No file named {}", name))?;
+        // Only files of the music directory can be played.
+        if !path.starts_with(&root) {
+            bail!("No file named {}", name);
+        }
+        let source = Restartable::ffmpeg(path, true)
+            .await
+            .context("Error sourcing ffmpeg")?;
+        Ok(vec![Song::Ready(source, query.to_string())])
+    }
+}
+
+/// Spotify tracks, albums and playlists, played from YouTube searches.
+pub struct Spotify;
+
+#[async_trait]
+impl SourceResolver for Spotify {
+    fn accepts(&self, query: &str) -> bool {
+        spotify::parse_link(query).is_some()
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let link = spotify::parse_link(query).ok_or_else(|| anyhow!("Invalid Spotify link"))?;
+        let tracks = spotify::fetch_tracks(&link, playlist_max_items())
+            .await
+            .context("Error fetching Spotify tracks")?;
+        // These sources only search YouTube once they start playing, so that
+        // large playlists are enqueued right away.
+        let mut sources = Vec::new();
+        for track in tracks {
+            match track.lazy_source().await {
+                Ok(source) => sources.push(Song::Ready(source, track.query())),
+                Err(why) => {
+                    println!("Err starting source: {:?}", why);
+                }
+            }
+        }
+        Ok(sources)
+    }
+}
+
+/// Single YouTube videos, linked without their tracking parameters.
+pub struct YoutubeVideo;
+
+#[async_trait]
+impl SourceResolver for YoutubeVideo {
+    fn accepts(&self, query: &str) -> bool {
+        youtube::parse_link(query).is_some_and(|link| link.video.is_some())
+    }
+
+    async fn resolve(&self, query: &str, _: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let url = youtube::parse_link(query)
+            .and_then(|link| link.video_only())
+            .ok_or_else(|| anyhow!("Invalid YouTube link"))?
+            .url();
+        let source = Restartable::ytdl(url.clone(), true)
+            .await
+            .context("Error sourcing ffmpeg")?;
+        Ok(vec![Song::Ready(source, url)])
+    }
+}
+
+/// YouTube playlists, listed with the configured backend. Links to a video
+/// within a playlist play the playlist from that video.
+pub struct YoutubePlaylist {
+    backend: PlaylistBackend,
+}
+
+#[async_trait]
+impl SourceResolver for YoutubePlaylist {
+    fn accepts(&self, query: &str) -> bool {
+        youtube::parse_link(query).is_some_and(|link| link.playlist.is_some())
+    }
+
+    async fn resolve(&self, query: &str, progress: &dyn Progress) -> anyhow::Result<Vec<Song>> {
+        let link = youtube::parse_link(query).ok_or_else(|| anyhow!("Invalid YouTube link"))?;
+        let playlist_id = link
+            .playlist
+            .ok_or_else(|| anyhow!("Invalid YouTube playlist"))?;
+        progress.report("Importing playlist...".to_string()).await;
+        let mut videos =
+            youtube::fetch_playlist(self.backend, &playlist_id, playlist_max_items(), progress)
+                .await?;
+        // The linked video may be past the imported songs.
+        let start = match &link.video {
+            Some(video) => videos.iter().position(|v| v == video),
+            None => Some(0),
+        };
+        let status = match start {
+            Some(start) => {
+                videos.drain(..start);
+                format!("Found {} songs in the playlist", videos.len())
+            }
+            None => format!(
+                "Found {} songs in the playlist, playing it from the start since the linked video is not among them",
+                videos.len()
+            ),
+        };
+        progress.report(status).await;
+        Ok(videos
+            .iter()
+            .map(|video| Song::Pending(youtube::video_url(video)))
+            .collect())
+    }
+}
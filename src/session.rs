@@ -0,0 +1,410 @@
+//! Per-guild sessions, from joining a voice channel to leaving it.
+//!
+//! A session owns the event handlers of the call, which are registered once
+//! when it starts.
+
+use std::{collections::HashMap, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+
+use futures::FutureExt;
+use serenity::{
+    async_trait,
+    builder::CreateEmbed,
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId, MessageId},
+        misc::Mentionable,
+    },
+    prelude::{Mutex, RwLock, TypeMapKey},
+};
+use songbird::{tracks::TrackHandle, Call, Event, EventContext, EventHandler, TrackEvent};
+
+use crate::{
+    autoplay::{self, History},
+    controller::guild_volume,
+    idle, persist, resolver,
+    settings::{self, NowPlaying},
+    state,
+    track::{self, TrackRequest},
+    LoopMode,
+};
+
+/// Interval between two snapshots of a playing queue.
+const SNAPSHOT_INTERVAL_SECS: u64 = 30;
+
+pub struct Session {
+    guild_id: GuildId,
+    ctx: Context,
+    /// Channel of the latest request, where the queue is restored.
+    text_channel: Mutex<ChannelId>,
+    /// The latest "Now playing" message.
+    announcement: Mutex<Option<(ChannelId, MessageId)>>,
+    /// Songs played during the session.
+    history: Mutex<History>,
+}
+
+impl Session {
+    pub async fn text_channel(&self) -> ChannelId {
+        *self.text_channel.lock().await
+    }
+
+    /// Enqueues a song related to `last`, the song the queue ran out on.
+    async fn autoplay(self: Arc<Self>, last: TrackHandle) {
+        // Tracks ending during the search must not wait on the history.
+        let history = self.history.lock().await.clone();
+        let related = autoplay::related(&self.ctx, &history, &last).await;
+        let (source, url) = match related {
+            Ok(song) => song,
+            Err(why) => {
+                println!("Error finding a related song: {:?}", why);
+                return;
+            }
+        };
+
+        let manager = songbird::get(&self.ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone();
+        if let Some(handler_lock) = manager.get(self.guild_id) {
+            let mut handler = handler_lock.lock().await;
+            // Songs requested in the meantime play instead.
+            if !handler.queue().is_empty() {
+                return;
+            }
+            // Related songs are announced where the last one was requested.
+            let channel = match track::request_of(&last).await {
+                Some(request) => request.channel,
+                None => self.text_channel().await,
+            };
+            let request = TrackRequest {
+                requester: self.ctx.cache.current_user_id().await,
+                channel,
+                url,
+            };
+            let volume = guild_volume(&self.ctx, self.guild_id).await;
+            track::enqueue(&mut handler, source, request, volume).await;
+            persist::save_call(self.guild_id, &handler, self.text_channel().await).await;
+        }
+        idle::reschedule(&self.ctx, self.guild_id).await;
+    }
+
+    /// Recreates finished tracks at the end of the queue, unless it was
+    /// stopped since `generation`.
+    async fn requeue(self: Arc<Self>, tracks: Vec<(String, TrackRequest)>, generation: u64) {
+        let resolvers = resolver::get(&self.ctx).await;
+        let mut requeued = Vec::with_capacity(tracks.len());
+        for (url, request) in tracks {
+            match resolvers.resolve_one(&url).await {
+                Ok(source) => requeued.push((source, request)),
+                Err(why) => println!("Err starting source: {:?}", why),
+            }
+        }
+
+        let manager = songbird::get(&self.ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone();
+        if let Some(handler_lock) = manager.get(self.guild_id) {
+            let mut handler = handler_lock.lock().await;
+            if state::get(&self.ctx, self.guild_id).await.import_generation != generation {
+                return;
+            }
+            let volume = guild_volume(&self.ctx, self.guild_id).await;
+            for (source, request) in requeued {
+                track::enqueue(&mut handler, source, request, volume).await;
+            }
+            persist::save_call(self.guild_id, &handler, self.text_channel().await).await;
+        }
+        idle::reschedule(&self.ctx, self.guild_id).await;
+    }
+}
+
+pub struct Sessions;
+
+impl TypeMapKey for Sessions {
+    type Value = Arc<RwLock<HashMap<GuildId, Arc<Session>>>>;
+}
+
+async fn sessions(ctx: &Context) -> Arc<RwLock<HashMap<GuildId, Arc<Session>>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<Sessions>()
+        .expect("Sessions placed in at initialisation.")
+        .clone()
+}
+
+/// Starts the session of `guild_id` on its call unless it already has one,
+/// and makes `text_channel` the channel of its latest request.
+pub async fn start(
+    ctx: &Context,
+    guild_id: GuildId,
+    handler: &mut Call,
+    text_channel: ChannelId,
+) -> Arc<Session> {
+    let sessions = sessions(ctx).await;
+    let mut sessions = sessions.write().await;
+    if let Some(session) = sessions.get(&guild_id) {
+        *session.text_channel.lock().await = text_channel;
+        return session.clone();
+    }
+
+    let session = Arc::new(Session {
+        guild_id,
+        ctx: ctx.clone(),
+        text_channel: Mutex::new(text_channel),
+        announcement: Mutex::new(None),
+        history: Mutex::new(History::default()),
+    });
+    handler.add_global_event(
+        Event::Track(TrackEvent::End),
+        Guarded(TrackEndNotifier {
+            session: session.clone(),
+        }),
+    );
+    handler.add_global_event(
+        Event::Track(TrackEvent::Play),
+        Guarded(TrackStartNotifier {
+            session: session.clone(),
+        }),
+    );
+    handler.add_global_event(
+        Event::Track(TrackEvent::Play),
+        Guarded(NowPlayingAnnouncer {
+            session: session.clone(),
+        }),
+    );
+    handler.add_global_event(
+        Event::Periodic(Duration::from_secs(SNAPSHOT_INTERVAL_SECS), None),
+        Guarded(QueueSnapshotter {
+            session: session.clone(),
+        }),
+    );
+    sessions.insert(guild_id, session.clone());
+    session
+}
+
+/// Ends the session of `guild_id`, once its call is removed.
+pub async fn end(ctx: &Context, guild_id: GuildId) {
+    sessions(ctx).await.write().await.remove(&guild_id);
+}
+
+/// Logs the panics of an event handler rather than letting them stop the
+/// events of the call.
+struct Guarded<H>(H);
+
+#[async_trait]
+impl<H: EventHandler> EventHandler for Guarded<H> {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        match AssertUnwindSafe(self.0.act(ctx)).catch_unwind().await {
+            Ok(event) => event,
+            Err(why) => {
+                println!("Event handler panicked: {:?}", why);
+                None
+            }
+        }
+    }
+}
+
+/// Loops tracks, autoplays related songs, and saves the queue as tracks end.
+struct TrackEndNotifier {
+    session: Arc<Session>,
+}
+
+#[async_trait]
+impl EventHandler for TrackEndNotifier {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let manager = songbird::get(&self.session.ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone();
+        let loop_mode = state::get(&self.session.ctx, self.session.guild_id)
+            .await
+            .loop_mode;
+        if let EventContext::Track(tracks) = ctx {
+            let mut history = self.session.history.lock().await;
+            for (_, handle) in tracks.iter() {
+                history.push(handle);
+            }
+        }
+
+        // In queue mode, finished tracks are recreated at the end of the queue.
+        // Retiring them first ensures that each one only comes back once.
+        // Resolving them takes a while, so it happens outside of the event loop.
+        let mut requeued = Vec::new();
+        if let (LoopMode::Queue, EventContext::Track(tracks)) = (loop_mode, ctx) {
+            for (_, handle) in tracks.iter() {
+                if track::retire(handle).await {
+                    continue;
+                }
+                let (url, request) = match (
+                    track::source_url(handle).await,
+                    track::request_of(handle).await,
+                ) {
+                    (Some(url), Some(request)) => (url, request),
+                    _ => continue,
+                };
+                requeued.push((url, request));
+            }
+        }
+        if !requeued.is_empty() {
+            let generation = state::get(&self.session.ctx, self.session.guild_id)
+                .await
+                .import_generation;
+            tokio::spawn(self.session.clone().requeue(requeued, generation));
+        }
+
+        let mut ran_out = false;
+        if let Some(handler_lock) = manager.get(self.session.guild_id) {
+            let handler = handler_lock.lock().await;
+            if loop_mode == LoopMode::Track {
+                if let Some(current) = handler.queue().current() {
+                    let _ = current.enable_loop();
+                }
+            }
+            persist::save_call(
+                self.session.guild_id,
+                &handler,
+                self.session.text_channel().await,
+            )
+            .await;
+            ran_out = handler.queue().is_empty();
+        }
+
+        // Autoplay picks up from the last song, unless the queue was stopped.
+        // Searching takes a while, so it happens outside of the event loop.
+        if ran_out && loop_mode == LoopMode::Off {
+            if let EventContext::Track([.., (_, last)]) = ctx {
+                let settings = settings::get(&self.session.ctx, self.session.guild_id).await;
+                if settings.autoplay && !track::is_retired(last).await {
+                    tokio::spawn(self.session.clone().autoplay((*last).clone()));
+                }
+            }
+        }
+        // The bot leaves once the queue stays empty for a while.
+        idle::reschedule(&self.session.ctx, self.session.guild_id).await;
+
+        None
+    }
+}
+
+/// Applies the guild's volume to tracks as they start.
+struct TrackStartNotifier {
+    session: Arc<Session>,
+}
+
+#[async_trait]
+impl EventHandler for TrackStartNotifier {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = ctx {
+            let volume = guild_volume(&self.session.ctx, self.session.guild_id).await;
+            for (_, handle) in tracks.iter() {
+                let _ = handle.set_volume(volume);
+            }
+        }
+
+        None
+    }
+}
+
+/// Announces songs as they start, in the announce channel or where they were
+/// requested.
+struct NowPlayingAnnouncer {
+    session: Arc<Session>,
+}
+
+#[async_trait]
+impl EventHandler for NowPlayingAnnouncer {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let handle = match ctx {
+            EventContext::Track([(_, handle), ..]) => *handle,
+            _ => return None,
+        };
+        // Tracks also fire this event when they are resumed.
+        if track::announce(handle).await {
+            return None;
+        }
+        let settings = settings::get(&self.session.ctx, self.session.guild_id).await;
+        if settings.now_playing == NowPlaying::Off {
+            return None;
+        }
+        let channel_id = match (settings.announce_channel, track::request_of(handle).await) {
+            (Some(channel), _) => ChannelId(channel),
+            (None, Some(request)) => request.channel,
+            (None, None) => self.session.text_channel().await,
+        };
+        let embed = now_playing_embed(handle).await;
+        let http = &self.session.ctx.http;
+
+        let mut announcement = self.session.announcement.lock().await;
+        if let (NowPlaying::Edit, Some((previous_channel, message_id))) =
+            (settings.now_playing, *announcement)
+        {
+            if previous_channel == channel_id
+                && channel_id
+                    .edit_message(http, message_id, |m| m.set_embed(embed.clone()))
+                    .await
+                    .is_ok()
+            {
+                return None;
+            }
+        }
+        match channel_id.send_message(http, |m| m.set_embed(embed)).await {
+            Ok(message) => *announcement = Some((channel_id, message.id)),
+            Err(why) => println!("Error sending message: {:?}", why),
+        }
+
+        None
+    }
+}
+
+async fn now_playing_embed(handle: &TrackHandle) -> CreateEmbed {
+    let metadata = handle.metadata();
+    let duration = metadata
+        .duration
+        .map(track::format_duration)
+        .unwrap_or_else(|| "Live".to_string());
+    let requester = track::request_of(handle)
+        .await
+        .map(|request| request.requester.mention().to_string())
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    let mut embed = CreateEmbed::default();
+    embed.author(|a| a.name("Now playing"));
+    embed.title(track::title_of(handle));
+    if let Some(url) = &metadata.source_url {
+        embed.url(url);
+    }
+    if let Some(thumbnail) = &metadata.thumbnail {
+        embed.thumbnail(thumbnail);
+    }
+    embed.field("Duration", duration, true);
+    embed.field("Requested by", requester, true);
+    embed
+}
+
+/// Periodically saves the queue, to keep the position in the current track
+/// up to date.
+struct QueueSnapshotter {
+    session: Arc<Session>,
+}
+
+#[async_trait]
+impl EventHandler for QueueSnapshotter {
+    async fn act(&self, _: &EventContext<'_>) -> Option<Event> {
+        let manager = songbird::get(&self.session.ctx)
+            .await
+            .expect("Songbird Voice client placed in at initialisation.")
+            .clone();
+        if let Some(handler_lock) = manager.get(self.session.guild_id) {
+            let handler = handler_lock.lock().await;
+            persist::save_call(
+                self.session.guild_id,
+                &handler,
+                self.session.text_channel().await,
+            )
+            .await;
+        }
+
+        None
+    }
+}
@@ -0,0 +1,449 @@
+//! Per-guild settings, persisted to disk.
+
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    framework::standard::{macros::command, Args, CommandResult},
+    model::{channel::Message, id::GuildId},
+    prelude::{RwLock, TypeMapKey},
+    utils::{parse_channel, parse_role},
+};
+use thiserror::Error;
+
+use crate::{
+    check_msg, controller::MusicController, invocation::Invocation, persist, usage, DEFAULT_PREFIX,
+    GENERAL_GROUP, MAX_VOLUME,
+};
+
+/// Names of the settings shown and changed by `~config`.
+pub const KEYS: &[&str] = &[
+    "prefix",
+    "volume",
+    "dj_role",
+    "dj_commands",
+    "vote_skip",
+    "announce_channel",
+    "now_playing",
+    "max_session",
+    "alone_timeout",
+    "empty_timeout",
+    "always_on",
+    "autoplay",
+];
+
+/// Longest timeout which can be set, a week in minutes.
+const MAX_TIMEOUT: u64 = 7 * 24 * 60;
+
+/// Commands which only DJs can use, unless a guild chooses its own.
+const DEFAULT_DJ_COMMANDS: &[&str] = &[
+    "play",
+    "skip",
+    "skipto",
+    "stop",
+    "remove",
+    "move",
+    "shuffle",
+    "removedupes",
+    "loop",
+    "volume",
+    "leave",
+];
+
+/// How songs are announced as they start.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NowPlaying {
+    Off,
+    /// Each song is announced in a new message.
+    On,
+    /// The message announcing the previous song is edited.
+    Edit,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Prefix of the commands.
+    pub prefix: String,
+    /// Playback volume, in percent.
+    pub volume: u32,
+    /// Role of the DJs.
+    pub dj_role: Option<u64>,
+    /// Commands which only DJs can use, once a DJ role is set.
+    pub dj_commands: Vec<String>,
+    /// Percentage of the listeners who must vote to skip a song, or 0 to
+    /// skip songs right away.
+    pub vote_skip: u32,
+    /// Channel where the bot announces songs, instead of the channel they were
+    /// requested in.
+    pub announce_channel: Option<u64>,
+    /// Whether songs are announced as they start.
+    pub now_playing: NowPlaying,
+    /// Minutes without any command after which the queue is paused, or 0 to
+    /// never pause it.
+    #[serde(alias = "idle_timeout")]
+    pub max_session: u64,
+    /// Minutes the bot stays alone in a voice channel before leaving.
+    pub alone_timeout: u64,
+    /// Minutes the bot stays in a voice channel once the queue is empty.
+    pub empty_timeout: u64,
+    /// Whether the bot stays in its voice channel until asked to leave.
+    pub always_on: bool,
+    /// Whether related songs are played once the queue runs out.
+    pub autoplay: bool,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            prefix: DEFAULT_PREFIX.to_string(),
+            volume: 100,
+            dj_role: None,
+            dj_commands: DEFAULT_DJ_COMMANDS.iter().map(|c| c.to_string()).collect(),
+            vote_skip: 0,
+            announce_channel: None,
+            now_playing: NowPlaying::On,
+            max_session: 120,
+            alone_timeout: 5,
+            empty_timeout: 5,
+            always_on: false,
+            autoplay: false,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SettingError {
+    #[error("Unknown setting {0}, expected one of: {keys}", keys = KEYS.join(", "))]
+    UnknownKey(String),
+    #[error("Expected {expected} for {key}")]
+    InvalidValue { key: String, expected: String },
+}
+
+impl GuildSettings {
+    /// Displays the value of the setting `key`.
+    pub fn value(&self, key: &str) -> Result<String, SettingError> {
+        let value = match key {
+            "prefix" => format!("`{}`", self.prefix),
+            "volume" => format!("{}%", self.volume),
+            "dj_role" => self
+                .dj_role
+                .map_or_else(|| "none".to_string(), |role| format!("<@&{}>", role)),
+            "dj_commands" if self.dj_commands.is_empty() => "none".to_string(),
+            "dj_commands" => self.dj_commands.join(", "),
+            "vote_skip" if self.vote_skip == 0 => "off".to_string(),
+            "vote_skip" => format!("{}%", self.vote_skip),
+            "announce_channel" => self
+                .announce_channel
+                .map_or_else(|| "none".to_string(), |channel| format!("<#{}>", channel)),
+            "now_playing" => match self.now_playing {
+                NowPlaying::Off => "off".to_string(),
+                NowPlaying::On => "on".to_string(),
+                NowPlaying::Edit => "edit".to_string(),
+            },
+            "max_session" if self.max_session == 0 => "never".to_string(),
+            "max_session" => format!("{} minutes", self.max_session),
+            "alone_timeout" => format!("{} minutes", self.alone_timeout),
+            "empty_timeout" => format!("{} minutes", self.empty_timeout),
+            "always_on" if self.always_on => "on".to_string(),
+            "always_on" => "off".to_string(),
+            "autoplay" if self.autoplay => "on".to_string(),
+            "autoplay" => "off".to_string(),
+            _ => return Err(SettingError::UnknownKey(key.to_string())),
+        };
+        Ok(value)
+    }
+
+    /// Parses `value` into the setting `key`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingError> {
+        let invalid = |expected: String| SettingError::InvalidValue {
+            key: key.to_string(),
+            expected,
+        };
+        match key {
+            "prefix" => {
+                if value.is_empty() || value.contains(char::is_whitespace) {
+                    return Err(invalid("a prefix without spaces".to_string()));
+                }
+                self.prefix = value.to_string();
+            }
+            "volume" => {
+                self.volume = value
+                    .trim_end_matches('%')
+                    .parse()
+                    .ok()
+                    .filter(|volume| *volume <= MAX_VOLUME)
+                    .ok_or_else(|| invalid(format!("a volume between 0 and {}", MAX_VOLUME)))?;
+            }
+            "dj_role" => {
+                self.dj_role = parse_id(value, |v| parse_role(v))
+                    .ok_or_else(|| invalid("a role, or none".to_string()))?;
+            }
+            "dj_commands" => {
+                self.dj_commands = parse_commands(value).ok_or_else(|| {
+                    invalid("a list of commands separated by commas, or none".to_string())
+                })?;
+            }
+            "vote_skip" => {
+                self.vote_skip = match value {
+                    "off" => Some(0),
+                    value => value.trim_end_matches('%').parse().ok(),
+                }
+                .filter(|percent| *percent <= 100)
+                .ok_or_else(|| invalid("a percentage of listeners, or off".to_string()))?;
+            }
+            "announce_channel" => {
+                self.announce_channel = parse_id(value, |v| parse_channel(v))
+                    .ok_or_else(|| invalid("a channel, or none".to_string()))?;
+            }
+            "now_playing" => {
+                self.now_playing = match value {
+                    "off" => NowPlaying::Off,
+                    "on" => NowPlaying::On,
+                    "edit" => NowPlaying::Edit,
+                    _ => return Err(invalid("on, off or edit".to_string())),
+                };
+            }
+            "max_session" => {
+                self.max_session = parse_timeout(value).ok_or_else(|| {
+                    invalid(format!(
+                        "a number of minutes up to {}, or 0 to never pause",
+                        MAX_TIMEOUT
+                    ))
+                })?;
+            }
+            "alone_timeout" => {
+                self.alone_timeout = parse_timeout(value)
+                    .ok_or_else(|| invalid(format!("a number of minutes up to {}", MAX_TIMEOUT)))?;
+            }
+            "empty_timeout" => {
+                self.empty_timeout = parse_timeout(value)
+                    .ok_or_else(|| invalid(format!("a number of minutes up to {}", MAX_TIMEOUT)))?;
+            }
+            "always_on" => {
+                self.always_on = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(invalid("on or off".to_string())),
+                };
+            }
+            "autoplay" => {
+                self.autoplay = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(invalid("on or off".to_string())),
+                };
+            }
+            _ => return Err(SettingError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Parses a number of minutes no longer than `MAX_TIMEOUT`.
+fn parse_timeout(value: &str) -> Option<u64> {
+    value.parse().ok().filter(|minutes| *minutes <= MAX_TIMEOUT)
+}
+
+/// Parses a list of command names separated by commas or spaces, or `none`.
+fn parse_commands(value: &str) -> Option<Vec<String>> {
+    if value == "none" {
+        return Some(Vec::new());
+    }
+    // Aliases are stored as the name of their command, which is what the DJ
+    // check compares.
+    let command_name = |name: &str| {
+        GENERAL_GROUP
+            .options
+            .commands
+            .iter()
+            .find(|command| command.options.names.contains(&name))
+            .and_then(|command| command.options.names.first())
+            .map(|name| name.to_string())
+    };
+    let mut names = Vec::new();
+    for name in value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+    {
+        let name = command_name(&name.to_lowercase())?;
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return None;
+    }
+    Some(names)
+}
+
+/// Parses an optional id given as a mention, as a raw id, or as `none`.
+fn parse_id(value: &str, parse_mention: fn(&str) -> Option<u64>) -> Option<Option<u64>> {
+    if value == "none" {
+        return Some(None);
+    }
+    parse_mention(value)
+        .or_else(|| value.parse().ok())
+        .map(Some)
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SettingsStore {
+    pub guilds: BTreeMap<u64, GuildSettings>,
+}
+
+impl TypeMapKey for SettingsStore {
+    type Value = Arc<RwLock<SettingsStore>>;
+}
+
+impl SettingsStore {
+    fn path() -> PathBuf {
+        persist::data_dir().join("settings.ron")
+    }
+
+    pub fn load() -> anyhow::Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(ron::de::from_str(&content)?)
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        fs::create_dir_all(persist::data_dir())?;
+        let content = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(Self::path(), content)?;
+        Ok(())
+    }
+}
+
+async fn store(ctx: &Context) -> Arc<RwLock<SettingsStore>> {
+    ctx.data
+        .read()
+        .await
+        .get::<SettingsStore>()
+        .expect("Settings store placed in at initialisation.")
+        .clone()
+}
+
+/// Returns a copy of the settings of `guild_id`.
+pub async fn get(ctx: &Context, guild_id: GuildId) -> GuildSettings {
+    store(ctx)
+        .await
+        .read()
+        .await
+        .guilds
+        .get(&guild_id.0)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Applies `f` to the settings of `guild_id`, and saves them.
+pub async fn update<F, O>(ctx: &Context, guild_id: GuildId, f: F) -> O
+where
+    F: FnOnce(&mut GuildSettings) -> O,
+{
+    let store = store(ctx).await;
+    let mut store = store.write().await;
+    let result = f(store.guilds.entry(guild_id.0).or_default());
+    if let Err(why) = store.save() {
+        println!("Error saving settings: {:?}", why);
+    }
+    result
+}
+
+#[command]
+#[only_in(guilds)]
+#[sub_commands(config_get, config_set)]
+async fn config(ctx: &Context, msg: &Message) -> CommandResult {
+    usage(ctx, msg, "config get [setting] | set <setting> <value>").await;
+
+    Ok(())
+}
+
+#[command("get")]
+#[only_in(guilds)]
+async fn config_get(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let key = args.single::<String>().ok();
+    show(ctx, &Invocation::from_message(msg), key).await
+}
+
+#[command("set")]
+#[only_in(guilds)]
+async fn config_set(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    match (args.single::<String>(), args.remains()) {
+        (Ok(key), Some(value)) => {
+            let value = value.to_string();
+            set(ctx, &Invocation::from_message(msg), key, value).await
+        }
+        _ => {
+            usage(ctx, msg, "config set <setting> <value>").await;
+
+            Ok(())
+        }
+    }
+}
+
+/// Whether the author can manage the server, which the settings require.
+async fn can_configure(ctx: &Context, inv: &Invocation) -> bool {
+    if inv.permissions(ctx).await.manage_guild() {
+        return true;
+    }
+    check_msg(
+        inv.reply(
+            ctx,
+            "You need the Manage Server permission to configure the bot",
+        )
+        .await,
+    );
+    false
+}
+
+/// Shows the setting `key`, or all of them.
+pub async fn show(ctx: &Context, inv: &Invocation, key: Option<String>) -> CommandResult {
+    if !can_configure(ctx, inv).await {
+        return Ok(());
+    }
+    let settings = get(ctx, inv.guild_id).await;
+    let content = match key {
+        Some(key) => match settings.value(&key) {
+            Ok(value) => format!("{}: {}", key, value),
+            Err(why) => why.to_string(),
+        },
+        None => KEYS
+            .iter()
+            .map(|key| format!("{}: {}", key, settings.value(key).unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    check_msg(inv.say(ctx, content).await);
+
+    Ok(())
+}
+
+pub async fn set(ctx: &Context, inv: &Invocation, key: String, value: String) -> CommandResult {
+    if !can_configure(ctx, inv).await {
+        return Ok(());
+    }
+    let result = update(ctx, inv.guild_id, |settings| {
+        settings.set(&key, value.trim())?;
+        settings.value(&key)
+    })
+    .await;
+    let content = match result {
+        Ok(value) => {
+            if key == "volume" {
+                MusicController::new(ctx).apply_volume(inv.guild_id).await;
+            }
+            format!("Set {} to {}", key, value)
+        }
+        Err(why) => why.to_string(),
+    };
+    check_msg(inv.say(ctx, content).await);
+
+    Ok(())
+}
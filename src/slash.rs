@@ -0,0 +1,546 @@
+//! Slash commands, running the same implementations as the prefix commands.
+
+use serde_json::Value;
+use serenity::{
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    framework::standard::CommandResult,
+    model::{
+        id::{GuildId, UserId},
+        interactions::{
+            application_command::{
+                ApplicationCommand, ApplicationCommandInteraction,
+                ApplicationCommandInteractionDataOption, ApplicationCommandOptionType,
+            },
+            autocomplete::AutocompleteInteraction,
+            Interaction, InteractionResponseType,
+        },
+    },
+    Result as SerenityResult,
+};
+
+use crate::{
+    check_msg, commands,
+    controller::MusicController,
+    dj, idle,
+    invocation::Invocation,
+    playlists::{self, Owner},
+    settings, track, LoopMode, MAX_VOLUME,
+};
+
+/// Maximum number of autocompletion choices accepted by Discord.
+const MAX_CHOICES: usize = 25;
+
+/// Maximum length of the name of an autocompletion choice.
+const MAX_CHOICE_NAME: usize = 100;
+
+/// Commands without options, with their description.
+const SIMPLE_COMMANDS: &[(&str, &str)] = &[
+    ("skip", "Skip the current song"),
+    ("stop", "Stop playing and clear the queue"),
+    ("pause", "Pause the queue"),
+    ("resume", "Resume the queue"),
+    ("shuffle", "Shuffle the queue"),
+    ("join", "Join your voice channel"),
+    ("leave", "Leave the voice channel"),
+    ("mute", "Mute the bot"),
+    ("unmute", "Unmute the bot"),
+    ("deafen", "Deafen the bot"),
+    ("undeafen", "Undeafen the bot"),
+    ("restore", "Restore the last saved queue"),
+    ("removedupes", "Remove duplicate songs from the queue"),
+    ("nowplaying", "Show the current song"),
+];
+
+/// Registers the slash commands globally, replacing any previous ones.
+pub async fn register(ctx: &Context) -> SerenityResult<Vec<ApplicationCommand>> {
+    ApplicationCommand::set_global_application_commands(&ctx.http, |commands| {
+        for (name, description) in SIMPLE_COMMANDS {
+            commands.create_application_command(|c| c.name(name).description(description));
+        }
+        commands
+            .create_application_command(|c| {
+                c.name("play")
+                    .description("Replace the queue with a song, playlist or search")
+                    .create_option(query_option)
+            })
+            .create_application_command(|c| {
+                c.name("queue")
+                    .description("Add a song, playlist or search to the queue")
+                    .create_option(query_option)
+            })
+            .create_application_command(|c| {
+                c.name("search")
+                    .description("Choose a song among the results of a YouTube search")
+                    .create_option(|o| {
+                        o.name("terms")
+                            .description("Terms to search on YouTube")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("list")
+                    .description("List the songs in the queue")
+                    .create_option(|o| {
+                        o.name("page")
+                            .description("Page to start at")
+                            .kind(ApplicationCommandOptionType::Integer)
+                            .min_int_value(1)
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("remove")
+                    .description("Remove a song, or a range of songs, from the queue")
+                    .create_option(|o| position_option(o, "start", "First song to remove", true))
+                    .create_option(|o| position_option(o, "end", "Last song to remove", false))
+            })
+            .create_application_command(|c| {
+                c.name("move")
+                    .description("Move a song in the queue")
+                    .create_option(|o| position_option(o, "from", "Song to move", true))
+                    .create_option(|o| position_option(o, "to", "Its new position", true))
+            })
+            .create_application_command(|c| {
+                c.name("skipto")
+                    .description("Skip to a song in the queue")
+                    .create_option(|o| position_option(o, "position", "Song to play", true))
+            })
+            .create_application_command(|c| {
+                c.name("loop")
+                    .description("Show or set the loop mode")
+                    .create_option(|o| {
+                        o.name("mode")
+                            .description("What to repeat")
+                            .kind(ApplicationCommandOptionType::String)
+                            .add_string_choice("Off", "off")
+                            .add_string_choice("Current track", "track")
+                            .add_string_choice("Whole queue", "queue")
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("volume")
+                    .description("Show or set the volume")
+                    .create_option(|o| {
+                        o.name("percent")
+                            .description("New volume, in percent")
+                            .kind(ApplicationCommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(MAX_VOLUME as i32)
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("seek")
+                    .description("Seek to a position in the current song")
+                    .create_option(|o| {
+                        o.name("position")
+                            .description("Timestamp such as 1:30")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("ff")
+                    .description("Fast-forward the current song")
+                    .create_option(seconds_option)
+            })
+            .create_application_command(|c| {
+                c.name("rw")
+                    .description("Rewind the current song")
+                    .create_option(seconds_option)
+            })
+            .create_application_command(|c| {
+                c.name("config")
+                    .description("Show or change the settings of the server")
+                    .create_option(|o| {
+                        o.name("get")
+                            .description("Show the settings")
+                            .kind(ApplicationCommandOptionType::SubCommand)
+                            .create_sub_option(|o| setting_option(o).required(false))
+                    })
+                    .create_option(|o| {
+                        o.name("set")
+                            .description("Change a setting")
+                            .kind(ApplicationCommandOptionType::SubCommand)
+                            .create_sub_option(|o| setting_option(o).required(true))
+                            .create_sub_option(|o| {
+                                o.name("value")
+                                    .description("The new value, or none")
+                                    .kind(ApplicationCommandOptionType::String)
+                                    .required(true)
+                            })
+                    })
+            })
+            .create_application_command(|c| {
+                c.name("playlist")
+                    .description("Manage saved playlists")
+                    .create_option(|o| {
+                        playlist_option(o, "save", "Save the queue as a playlist", false)
+                    })
+                    .create_option(|o| {
+                        playlist_option(o, "load", "Add a playlist to the queue", true)
+                    })
+                    .create_option(|o| {
+                        o.name("list")
+                            .description("List the saved playlists")
+                            .kind(ApplicationCommandOptionType::SubCommand)
+                    })
+                    .create_option(|o| playlist_option(o, "delete", "Delete a playlist", true))
+                    .create_option(|o| {
+                        playlist_option(o, "add", "Add a song to a playlist", true)
+                            .create_sub_option(|o| {
+                                o.name("url")
+                                    .description("Link to the song")
+                                    .kind(ApplicationCommandOptionType::String)
+                                    .required(true)
+                            })
+                    })
+            })
+    })
+    .await
+}
+
+fn query_option(o: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    o.name("query")
+        .description("A link, or terms to search on YouTube")
+        .kind(ApplicationCommandOptionType::String)
+        .required(true)
+}
+
+fn setting_option(o: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    o.name("setting")
+        .description("Name of the setting")
+        .kind(ApplicationCommandOptionType::String);
+    for key in settings::KEYS {
+        o.add_string_choice(key, key);
+    }
+    o
+}
+
+fn seconds_option(o: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    o.name("seconds")
+        .description("Number of seconds")
+        .kind(ApplicationCommandOptionType::Integer)
+        .min_int_value(1)
+        .required(true)
+}
+
+/// A position in the queue, completed with the titles of the songs.
+fn position_option<'a>(
+    o: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+    required: bool,
+) -> &'a mut CreateApplicationCommandOption {
+    o.name(name)
+        .description(description)
+        .kind(ApplicationCommandOptionType::Integer)
+        .min_int_value(1)
+        .required(required)
+        .set_autocomplete(true)
+}
+
+/// A playlist subcommand, taking the name of the playlist and its owner.
+fn playlist_option<'a>(
+    o: &'a mut CreateApplicationCommandOption,
+    name: &str,
+    description: &str,
+    autocomplete: bool,
+) -> &'a mut CreateApplicationCommandOption {
+    o.name(name)
+        .description(description)
+        .kind(ApplicationCommandOptionType::SubCommand)
+        .create_sub_option(|o| {
+            o.name("name")
+                .description("Name of the playlist")
+                .kind(ApplicationCommandOptionType::String)
+                .required(true)
+                .set_autocomplete(autocomplete)
+        })
+        .create_sub_option(|o| {
+            o.name("mine")
+                .description("Use your own playlists rather than the server's")
+                .kind(ApplicationCommandOptionType::Boolean)
+        })
+}
+
+/// Runs slash commands and answers autocompletion requests.
+pub async fn handle(ctx: &Context, interaction: Interaction) {
+    match interaction {
+        Interaction::ApplicationCommand(command) => run(ctx, command).await,
+        Interaction::Autocomplete(autocomplete) => complete(ctx, autocomplete).await,
+        _ => {}
+    }
+}
+
+async fn run(ctx: &Context, command: ApplicationCommandInteraction) {
+    if command.guild_id.is_none() {
+        let responded = command
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| d.content("Commands only work in servers"))
+            })
+            .await;
+        if let Err(why) = responded {
+            println!("Error responding to interaction: {:?}", why);
+        }
+
+        return;
+    }
+
+    // Resolving songs takes longer than Discord waits for an answer, so the
+    // answer is deferred and sent once the command is done.
+    if let Err(why) = command.defer(&ctx.http).await {
+        println!("Error deferring interaction: {:?}", why);
+
+        return;
+    }
+
+    let name = command.data.name.clone();
+    let options = command.data.options.clone();
+    let inv = Invocation::from_interaction(command).unwrap();
+
+    // The checks of the prefix commands don't apply to slash commands.
+    let range = integer(&options, "start").map(|start| {
+        let end = integer(&options, "end").unwrap_or(start);
+        (start as usize, end as usize)
+    });
+    if !dj::allowed(ctx, inv.guild_id, inv.author, &name, range).await {
+        check_msg(inv.say(ctx, dj::DJ_ONLY).await);
+
+        return;
+    }
+
+    if let Err(why) = dispatch(ctx, &inv, &name, &options).await {
+        println!("Command '{}' returned error {:?}", name, why);
+        check_msg(inv.say(ctx, format!("Failed: {}", why)).await);
+    }
+    // Some commands, such as `join`, have nothing to say when they succeed.
+    if !inv.responded() {
+        check_msg(inv.say(ctx, "Done").await);
+    }
+    idle::activity(ctx, inv.guild_id, inv.channel_id).await;
+}
+
+async fn dispatch(
+    ctx: &Context,
+    inv: &Invocation,
+    name: &str,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> CommandResult {
+    match name {
+        "play" => commands::play(ctx, inv, string(options, "query").unwrap_or_default()).await,
+        "queue" => commands::queue(ctx, inv, string(options, "query").unwrap_or_default()).await,
+        "search" => commands::search(ctx, inv, string(options, "terms").unwrap_or_default()).await,
+        "skip" => commands::skip(ctx, inv).await,
+        "stop" => commands::stop(ctx, inv).await,
+        "pause" => commands::pause(ctx, inv).await,
+        "resume" => commands::resume(ctx, inv).await,
+        "shuffle" => commands::shuffle(ctx, inv).await,
+        "join" => commands::join(ctx, inv).await,
+        "leave" => commands::leave(ctx, inv).await,
+        "mute" => commands::mute(ctx, inv).await,
+        "unmute" => commands::unmute(ctx, inv).await,
+        "deafen" => commands::deafen(ctx, inv).await,
+        "undeafen" => commands::undeafen(ctx, inv).await,
+        "restore" => commands::restore(ctx, inv).await,
+        "removedupes" => commands::removedupes(ctx, inv).await,
+        "nowplaying" => commands::nowplaying(ctx, inv).await,
+        "list" => {
+            commands::list(ctx, inv, integer(options, "page").map(|page| page as usize)).await
+        }
+        "remove" => {
+            let start = integer(options, "start").unwrap_or_default() as usize;
+            let end = integer(options, "end").map_or(start, |end| end as usize);
+            commands::remove(ctx, inv, start, end).await
+        }
+        "move" => {
+            let from = integer(options, "from").unwrap_or_default() as usize;
+            let to = integer(options, "to").unwrap_or_default() as usize;
+            commands::move_track(ctx, inv, from, to).await
+        }
+        "skipto" => {
+            let index = integer(options, "position").unwrap_or_default() as usize;
+            commands::skipto(ctx, inv, index).await
+        }
+        "loop" => {
+            let mode = string(options, "mode").and_then(|mode| LoopMode::parse(&mode));
+            commands::loop_mode(ctx, inv, mode).await
+        }
+        "volume" => {
+            let volume = integer(options, "percent").map(|volume| volume as u32);
+            commands::volume(ctx, inv, volume).await
+        }
+        "seek" => match string(options, "position").and_then(|p| track::parse_timestamp(&p)) {
+            Some(position) => commands::seek(ctx, inv, position).await,
+            None => {
+                check_msg(inv.say(ctx, "Expected a timestamp such as 1:30").await);
+
+                Ok(())
+            }
+        },
+        "ff" => commands::ff(ctx, inv, integer(options, "seconds").unwrap_or_default()).await,
+        "rw" => commands::rw(ctx, inv, integer(options, "seconds").unwrap_or_default()).await,
+        "playlist" => playlist(ctx, inv, options).await,
+        "config" => config(ctx, inv, options).await,
+        _ => {
+            check_msg(inv.say(ctx, format!("Unknown command: {}", name)).await);
+
+            Ok(())
+        }
+    }
+}
+
+async fn playlist(
+    ctx: &Context,
+    inv: &Invocation,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> CommandResult {
+    let subcommand = match options.first() {
+        Some(subcommand) => subcommand,
+        None => return Ok(()),
+    };
+    let options = &subcommand.options;
+    let owner = owner(inv.guild_id, inv.author, options);
+    let name = string(options, "name").unwrap_or_default();
+
+    match subcommand.name.as_str() {
+        "save" => playlists::save(ctx, inv, owner, name).await,
+        "load" => playlists::load(ctx, inv, owner, &name).await,
+        "list" => playlists::list(ctx, inv).await,
+        "delete" => playlists::delete(ctx, inv, owner, &name).await,
+        "add" => {
+            let url = string(options, "url").unwrap_or_default();
+            playlists::add(ctx, inv, owner, name, url).await
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn config(
+    ctx: &Context,
+    inv: &Invocation,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> CommandResult {
+    let subcommand = match options.first() {
+        Some(subcommand) => subcommand,
+        None => return Ok(()),
+    };
+    let options = &subcommand.options;
+    let key = string(options, "setting");
+
+    match subcommand.name.as_str() {
+        "get" => settings::show(ctx, inv, key).await,
+        "set" => {
+            let value = string(options, "value").unwrap_or_default();
+            settings::set(ctx, inv, key.unwrap_or_default(), value).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The owner selected by the `mine` option of playlist subcommands.
+fn owner(
+    guild_id: GuildId,
+    user_id: UserId,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> Owner {
+    if value(options, "mine").and_then(Value::as_bool) == Some(true) {
+        Owner::User(user_id)
+    } else {
+        Owner::Guild(guild_id)
+    }
+}
+
+async fn complete(ctx: &Context, autocomplete: AutocompleteInteraction) {
+    let guild_id = match autocomplete.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    // The options of playlist subcommands are nested in the subcommand.
+    let is_playlist = autocomplete.data.name == "playlist";
+    let options = match autocomplete.data.options.first() {
+        Some(subcommand) if is_playlist => subcommand.options.as_slice(),
+        _ => autocomplete.data.options.as_slice(),
+    };
+    let typed = match options.iter().find(|option| option.focused) {
+        Some(option) => match &option.value {
+            Some(Value::String(typed)) => typed.to_lowercase(),
+            Some(value) => value.to_string(),
+            None => String::new(),
+        },
+        None => return,
+    };
+
+    let responded = if is_playlist {
+        let owner = owner(guild_id, autocomplete.user.id, options);
+        let names = playlists::names(ctx, owner).await;
+        autocomplete
+            .create_autocomplete_response(&ctx.http, |r| {
+                for name in names
+                    .iter()
+                    .filter(|name| name.to_lowercase().contains(&typed))
+                    .take(MAX_CHOICES)
+                {
+                    r.add_string_choice(name, name);
+                }
+                r
+            })
+            .await
+    } else {
+        let positions = queue_positions(ctx, guild_id).await;
+        autocomplete
+            .create_autocomplete_response(&ctx.http, |r| {
+                for (i, title) in positions
+                    .iter()
+                    .filter(|(i, title)| {
+                        i.to_string().starts_with(&typed) || title.to_lowercase().contains(&typed)
+                    })
+                    .take(MAX_CHOICES)
+                {
+                    let name = format!("{}. {}", i, title)
+                        .chars()
+                        .take(MAX_CHOICE_NAME)
+                        .collect::<String>();
+                    r.add_int_choice(name, *i as i64);
+                }
+                r
+            })
+            .await
+    };
+    if let Err(why) = responded {
+        println!("Error answering autocompletion: {:?}", why);
+    }
+}
+
+/// The upcoming songs of the queue, with their positions.
+async fn queue_positions(ctx: &Context, guild_id: GuildId) -> Vec<(usize, String)> {
+    MusicController::new(ctx)
+        .queue(guild_id)
+        .await
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, handle)| (i, track::title_of(handle)))
+        .collect()
+}
+
+fn value<'a>(
+    options: &'a [ApplicationCommandInteractionDataOption],
+    name: &str,
+) -> Option<&'a Value> {
+    options
+        .iter()
+        .find(|option| option.name == name)?
+        .value
+        .as_ref()
+}
+
+fn string(options: &[ApplicationCommandInteractionDataOption], name: &str) -> Option<String> {
+    value(options, name)?.as_str().map(str::to_string)
+}
+
+fn integer(options: &[ApplicationCommandInteractionDataOption], name: &str) -> Option<u64> {
+    value(options, name)?.as_u64()
+}
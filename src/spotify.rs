@@ -0,0 +1,332 @@
+//! Resolution of Spotify links to YouTube searches.
+
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context as _};
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serenity::{async_trait, prelude::Mutex};
+use songbird::input::{
+    error::Result as InputResult,
+    restartable::{Restart, Restartable},
+    Codec, Container, Input, Metadata,
+};
+
+lazy_static! {
+    /// Access token of the client credentials flow, with its expiry.
+    static ref TOKEN: Mutex<Option<(String, Instant)>> = Mutex::new(None);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpotifyLink {
+    Track(String),
+    Album(String),
+    Playlist(String),
+}
+
+/// Recognizes `https://open.spotify.com/<kind>/<id>` and `spotify:<kind>:<id>` links.
+pub fn parse_link(url: &str) -> Option<SpotifyLink> {
+    let path = if let Some(uri) = url.strip_prefix("spotify:") {
+        uri.replace(':', "/")
+    } else {
+        let path = url
+            .strip_prefix("https://open.spotify.com/")
+            .or_else(|| url.strip_prefix("http://open.spotify.com/"))?;
+        path.split(['?', '#']).next()?.to_string()
+    };
+
+    let mut parts = path.split('/').filter(|part| !part.starts_with("intl-"));
+    let kind = parts.next()?;
+    let id = parts.next().filter(|id| !id.is_empty())?.to_string();
+    match kind {
+        "track" => Some(SpotifyLink::Track(id)),
+        "album" => Some(SpotifyLink::Album(id)),
+        "playlist" => Some(SpotifyLink::Playlist(id)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpotifyTrack {
+    pub name: String,
+    pub artists: Vec<String>,
+    pub duration: Duration,
+    pub thumbnail: Option<String>,
+}
+
+impl SpotifyTrack {
+    /// The YouTube search used to play this track.
+    pub fn query(&self) -> String {
+        format!("{} {}", self.artists.join(" "), self.name)
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            track: Some(self.name.clone()),
+            artist: Some(self.artists.join(", ")),
+            title: Some(format!("{} - {}", self.artists.join(", "), self.name)),
+            duration: Some(self.duration),
+            thumbnail: self.thumbnail.clone(),
+            // Matches the output of youtube-dl through ffmpeg.
+            channels: Some(2),
+            sample_rate: Some(48000),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a source which only searches YouTube once it starts playing.
+    pub async fn lazy_source(&self) -> InputResult<Restartable> {
+        let restarter = SearchRestarter {
+            query: self.query(),
+            metadata: self.metadata(),
+        };
+        Restartable::new(restarter, true).await
+    }
+}
+
+/// Searches YouTube when the track is first played, rather than at creation
+/// like `Restartable::ytdl_search`.
+struct SearchRestarter {
+    query: String,
+    metadata: Metadata,
+}
+
+#[async_trait]
+impl Restart for SearchRestarter {
+    async fn call_restart(&mut self, time: Option<Duration>) -> InputResult<Input> {
+        let source = Restartable::ytdl_search(&self.query, false).await?;
+        let mut input = Input::from(source);
+        if let Some(time) = time {
+            input.seek_time(time);
+        }
+        Ok(input)
+    }
+
+    async fn lazy_init(&mut self) -> InputResult<(Option<Metadata>, Codec, Container)> {
+        Ok((Some(self.metadata.clone()), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Token {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Track {
+    name: String,
+    artists: Vec<Artist>,
+    duration_ms: u64,
+    album: Option<Album>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Artist {
+    name: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Album {
+    images: Vec<Image>,
+    tracks: Page<Track>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct Image {
+    url: String,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct PlaylistItem {
+    track: Option<Track>,
+}
+
+impl Track {
+    fn into_spotify_track(self, thumbnail: Option<String>) -> SpotifyTrack {
+        SpotifyTrack {
+            thumbnail: self
+                .album
+                .and_then(|album| album.images.into_iter().next())
+                .map(|image| image.url)
+                .or(thumbnail),
+            name: self.name,
+            artists: self.artists.into_iter().map(|artist| artist.name).collect(),
+            duration: Duration::from_millis(self.duration_ms),
+        }
+    }
+}
+
+/// Returns a valid access token, requesting a new one with the credentials
+/// from `SPOTIFY_CLIENT_ID` and `SPOTIFY_CLIENT_SECRET` if needed.
+async fn token(client: &reqwest::Client) -> anyhow::Result<String> {
+    let mut cached = TOKEN.lock().await;
+    if let Some((token, expiry)) = &*cached {
+        if Instant::now() < *expiry {
+            return Ok(token.clone());
+        }
+    }
+
+    let id =
+        env::var("SPOTIFY_CLIENT_ID").context("Expected SPOTIFY_CLIENT_ID in the environment")?;
+    let secret = env::var("SPOTIFY_CLIENT_SECRET")
+        .context("Expected SPOTIFY_CLIENT_SECRET in the environment")?;
+    let token = client
+        .post("https://accounts.spotify.com/api/token")
+        .basic_auth(id, Some(secret))
+        .form(&[("grant_type", "client_credentials")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Token>()
+        .await?;
+
+    // Renew the token a bit before it actually expires.
+    let expiry = Instant::now() + Duration::from_secs(token.expires_in.saturating_sub(60));
+    *cached = Some((token.access_token.clone(), expiry));
+    Ok(token.access_token)
+}
+
+async fn get<T: DeserializeOwned>(client: &reqwest::Client, url: &str) -> anyhow::Result<T> {
+    let token = token(client).await?;
+    Ok(client
+        .get(url)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await?)
+}
+
+/// Fetches the tracks behind a link, up to `max_items` of them.
+pub async fn fetch_tracks(
+    link: &SpotifyLink,
+    max_items: usize,
+) -> anyhow::Result<Vec<SpotifyTrack>> {
+    let client = reqwest::Client::new();
+    let mut tracks = Vec::new();
+
+    match link {
+        SpotifyLink::Track(id) => {
+            let url = format!("https://api.spotify.com/v1/tracks/{}", id);
+            let track = get::<Track>(&client, &url).await?;
+            tracks.push(track.into_spotify_track(None));
+        }
+        SpotifyLink::Album(id) => {
+            let url = format!("https://api.spotify.com/v1/albums/{}", id);
+            let album = get::<Album>(&client, &url).await?;
+            let thumbnail = album.images.into_iter().next().map(|image| image.url);
+            let mut page = album.tracks;
+            loop {
+                tracks.extend(
+                    page.items
+                        .into_iter()
+                        .map(|track| track.into_spotify_track(thumbnail.clone())),
+                );
+                match page.next {
+                    Some(next) if tracks.len() < max_items => {
+                        page = get::<Page<Track>>(&client, &next).await?;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        SpotifyLink::Playlist(id) => {
+            let mut next = Some(format!(
+                "https://api.spotify.com/v1/playlists/{}/tracks?limit=100",
+                id
+            ));
+            while let Some(url) = next {
+                if tracks.len() >= max_items {
+                    break;
+                }
+                let page = get::<Page<PlaylistItem>>(&client, &url).await?;
+                // Tracks which were removed from Spotify show up as `null`.
+                tracks.extend(
+                    page.items
+                        .into_iter()
+                        .filter_map(|item| item.track)
+                        .map(|track| track.into_spotify_track(None)),
+                );
+                next = page.next;
+            }
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(anyhow!("No tracks found"));
+    }
+    tracks.truncate(max_items);
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_links() {
+        let track = Some(SpotifyLink::Track("4uLU6hMCjMI75M1A2tKUQC".to_string()));
+        assert_eq!(
+            parse_link("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"),
+            track
+        );
+        assert_eq!(
+            parse_link("http://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"),
+            track
+        );
+        assert_eq!(
+            parse_link("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc123"),
+            track
+        );
+        assert_eq!(
+            parse_link("https://open.spotify.com/intl-fr/track/4uLU6hMCjMI75M1A2tKUQC"),
+            track
+        );
+        assert_eq!(parse_link("spotify:track:4uLU6hMCjMI75M1A2tKUQC"), track);
+        assert_eq!(
+            parse_link("https://open.spotify.com/album/1DFixLWuPkv3KT3TnV35m3"),
+            Some(SpotifyLink::Album("1DFixLWuPkv3KT3TnV35m3".to_string()))
+        );
+        assert_eq!(
+            parse_link("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M"),
+            Some(SpotifyLink::Playlist("37i9dQZF1DXcBWIGoYBM5M".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_other_links() {
+        assert_eq!(parse_link("https://open.spotify.com/track/"), None);
+        assert_eq!(
+            parse_link("https://open.spotify.com/track/?si=abc123"),
+            None
+        );
+        assert_eq!(parse_link("spotify:track:"), None);
+        assert_eq!(
+            parse_link("https://open.spotify.com/artist/0gxyHStUsqpMadRV0Di1Qt"),
+            None
+        );
+        assert_eq!(
+            parse_link("https://example.com/track/4uLU6hMCjMI75M1A2tKUQC"),
+            None
+        );
+        assert_eq!(parse_link("never gonna give you up"), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! Per-guild playback state, kept in the client's data.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Instant,
+};
+
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+    prelude::{RwLock, TypeMapKey},
+};
+use songbird::tracks::TrackHandle;
+use tokio::task::AbortHandle;
+
+/// What happens to tracks once they end.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    #[default]
+    Off,
+    /// The current track repeats.
+    Track,
+    /// Finished tracks go back to the end of the queue.
+    Queue,
+}
+
+impl LoopMode {
+    /// Parses `off`, `track` or `queue`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "off" => Some(LoopMode::Off),
+            "track" => Some(LoopMode::Track),
+            "queue" => Some(LoopMode::Queue),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct GuildState {
+    pub loop_mode: LoopMode,
+    /// Listeners who voted to skip `skip_votes_track`.
+    pub skip_votes: HashSet<UserId>,
+    /// The track the skip votes were cast on.
+    pub skip_votes_track: Option<TrackHandle>,
+    /// When the last command was used.
+    pub last_activity: Option<Instant>,
+    /// Channel where the last command was used.
+    pub text_channel: Option<ChannelId>,
+    /// Whether the queue was paused because nobody was listening.
+    pub paused_alone: bool,
+    /// The members who were listening when the voice channel last changed.
+    pub listeners: HashSet<UserId>,
+    /// The task which makes the bot leave once idle.
+    pub idle_timer: Option<AbortHandle>,
+    /// Incremented to cancel the imports loading songs in the background.
+    pub import_generation: u64,
+}
+
+pub struct GuildStates;
+
+impl TypeMapKey for GuildStates {
+    type Value = Arc<RwLock<HashMap<GuildId, GuildState>>>;
+}
+
+async fn states(ctx: &Context) -> Arc<RwLock<HashMap<GuildId, GuildState>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<GuildStates>()
+        .expect("Guild states placed in at initialisation.")
+        .clone()
+}
+
+/// Returns a copy of the state of `guild_id`.
+pub async fn get(ctx: &Context, guild_id: GuildId) -> GuildState {
+    states(ctx)
+        .await
+        .read()
+        .await
+        .get(&guild_id)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Applies `f` to the state of `guild_id`.
+pub async fn update<F, O>(ctx: &Context, guild_id: GuildId, f: F) -> O
+where
+    F: FnOnce(&mut GuildState) -> O,
+{
+    let states = states(ctx).await;
+    let mut states = states.write().await;
+    f(states.entry(guild_id).or_default())
+}
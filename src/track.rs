@@ -0,0 +1,191 @@
+use std::time::Duration;
+
+use serenity::{
+    model::id::{ChannelId, UserId},
+    prelude::TypeMapKey,
+};
+use songbird::{
+    input::restartable::Restartable,
+    tracks::{create_player, TrackHandle},
+    Call,
+};
+
+/// Who asked for a track, where, and what they asked for.
+///
+/// Stored in the typemap of every track we enqueue.
+#[derive(Clone, Debug)]
+pub struct TrackRequest {
+    pub requester: UserId,
+    /// Channel of the request, where the track is announced.
+    pub channel: ChannelId,
+    pub url: String,
+}
+
+impl TypeMapKey for TrackRequest {
+    type Value = TrackRequest;
+}
+
+/// Marks a track which must not come back to the queue once it ends.
+pub struct Retired;
+
+impl TypeMapKey for Retired {
+    type Value = ();
+}
+
+/// Marks the track as retired, returning whether it already was.
+pub async fn retire(handle: &TrackHandle) -> bool {
+    let mut typemap = handle.typemap().write().await;
+    let retired = typemap.contains_key::<Retired>();
+    typemap.insert::<Retired>(());
+    retired
+}
+
+/// Whether the track was retired, such as by `~stop`.
+pub async fn is_retired(handle: &TrackHandle) -> bool {
+    handle.typemap().read().await.contains_key::<Retired>()
+}
+
+/// Marks a track which was announced as it started.
+pub struct Announced;
+
+impl TypeMapKey for Announced {
+    type Value = ();
+}
+
+/// Marks the track as announced, returning whether it already was.
+pub async fn announce(handle: &TrackHandle) -> bool {
+    let mut typemap = handle.typemap().write().await;
+    let announced = typemap.contains_key::<Announced>();
+    typemap.insert::<Announced>(());
+    announced
+}
+
+/// Retires and stops a track which is taken out of the queue.
+pub async fn discard(handle: &TrackHandle) {
+    retire(handle).await;
+    let _ = handle.stop();
+}
+
+/// Adds `source` to the call's queue at the given `volume`, tagged with `request`.
+pub async fn enqueue(
+    handler: &mut Call,
+    source: Restartable,
+    request: TrackRequest,
+    volume: f32,
+) -> TrackHandle {
+    let (mut track, handle) = create_player(source.into());
+    track.set_volume(volume);
+    handle
+        .typemap()
+        .write()
+        .await
+        .insert::<TrackRequest>(request);
+    // Tracks only fire `TrackEvent::Play` when they go from paused to playing,
+    // which the first track of the queue never does unless it starts paused.
+    let first = handler.queue().is_empty();
+    if first {
+        track.pause();
+    }
+    handler.enqueue(track);
+    if first {
+        let _ = handle.play();
+    }
+    handle
+}
+
+/// Returns the request attached to `handle`, if it was enqueued through [`enqueue`].
+pub async fn request_of(handle: &TrackHandle) -> Option<TrackRequest> {
+    handle.typemap().read().await.get::<TrackRequest>().cloned()
+}
+
+/// The URL a track can be recreated from.
+pub async fn source_url(handle: &TrackHandle) -> Option<String> {
+    match &handle.metadata().source_url {
+        Some(url) => Some(url.clone()),
+        None => request_of(handle).await.map(|r| r.url),
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` for an hour or more.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Parses a timestamp such as `90`, `1:30` or `1:02:30`.
+pub fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut secs: u64 = 0;
+    for part in timestamp.trim().split(':') {
+        secs = secs.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    Some(Duration::from_secs(secs))
+}
+
+/// Parses a queue position or an inclusive range of positions, such as `3` or `3-7`.
+pub fn parse_range(arg: &str) -> Option<(usize, usize)> {
+    match arg.split_once('-') {
+        Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+        None => {
+            let index = arg.trim().parse().ok()?;
+            Some((index, index))
+        }
+    }
+}
+
+/// Renders a text progress bar of `width` characters.
+pub fn progress_bar(position: Duration, duration: Duration, width: usize) -> String {
+    let filled = if duration.as_secs_f64() > 0.0 {
+        let ratio = (position.as_secs_f64() / duration.as_secs_f64()).min(1.0);
+        (ratio * width as f64) as usize
+    } else {
+        0
+    };
+    (0..width)
+        .map(|i| if i == filled { '🔘' } else { '▬' })
+        .collect()
+}
+
+/// A human-readable name for the track.
+pub fn title_of(handle: &TrackHandle) -> String {
+    let metadata = handle.metadata();
+    metadata
+        .title
+        .clone()
+        .or_else(|| metadata.track.clone())
+        .or_else(|| metadata.source_url.clone())
+        .unwrap_or_else(|| "Unknown track".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_positions_and_ranges() {
+        assert_eq!(parse_range("3"), Some((3, 3)));
+        assert_eq!(parse_range(" 3-7 "), Some((3, 7)));
+        assert_eq!(parse_range("3 - 7"), Some((3, 7)));
+        assert_eq!(parse_range(""), None);
+        assert_eq!(parse_range("3-"), None);
+        assert_eq!(parse_range("a-b"), None);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1:30"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timestamp("1:02:30"), Some(Duration::from_secs(3750)));
+        assert_eq!(parse_timestamp("1:x"), None);
+        assert_eq!(parse_timestamp("18446744073709551615:00"), None);
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(65)), "1:05");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+}
@@ -0,0 +1,355 @@
+//! Listing of the videos of YouTube playlists and searches.
+
+use std::{env, time::Duration};
+
+use anyhow::{bail, Context as _};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::resolver::Progress;
+
+/// The program songbird plays YouTube videos with.
+const YOUTUBE_DL_COMMAND: &str = "youtube-dl";
+
+/// How the videos of a playlist are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaylistBackend {
+    /// The YouTube Data API, which needs a `GOOGLE_TOKEN`.
+    Api,
+    /// The flat playlist output of youtube-dl.
+    Ytdl,
+}
+
+impl PlaylistBackend {
+    /// Reads the backend from `YOUTUBE_PLAYLIST_BACKEND`, either `api` or
+    /// `ytdl`. Defaults to the API when `GOOGLE_TOKEN` is set.
+    pub fn from_env() -> Self {
+        match env::var("YOUTUBE_PLAYLIST_BACKEND").as_deref() {
+            Ok("api") => Self::Api,
+            Ok("ytdl") => Self::Ytdl,
+            Ok(other) => {
+                println!("Unknown playlist backend {}, using the default", other);
+                Self::default_from_env()
+            }
+            Err(_) => Self::default_from_env(),
+        }
+    }
+
+    fn default_from_env() -> Self {
+        if env::var("GOOGLE_TOKEN").is_ok() {
+            Self::Api
+        } else {
+            Self::Ytdl
+        }
+    }
+}
+
+/// A link to a YouTube video, a playlist, or a video within a playlist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YoutubeLink {
+    pub video: Option<String>,
+    pub playlist: Option<String>,
+}
+
+impl YoutubeLink {
+    /// The canonical URL of the link, without tracking parameters.
+    pub fn url(&self) -> String {
+        match (&self.video, &self.playlist) {
+            (Some(video), Some(playlist)) => format!("{}&list={}", video_url(video), playlist),
+            (Some(video), None) => video_url(video),
+            (None, Some(playlist)) => playlist_url(playlist),
+            (None, None) => unreachable!("Links have a video or a playlist"),
+        }
+    }
+
+    /// The same link without its playlist.
+    pub fn video_only(&self) -> Option<Self> {
+        Some(Self {
+            video: Some(self.video.clone()?),
+            playlist: None,
+        })
+    }
+}
+
+/// Recognizes the links of `youtube.com`, including its `www.`, `m.` and
+/// `music.` subdomains, and of `youtu.be`.
+pub fn parse_link(url: &str) -> Option<YoutubeLink> {
+    let url = Url::parse(url).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    let host = url.host_str()?;
+    let host = ["www.", "m.", "music."]
+        .iter()
+        .find_map(|sub| host.strip_prefix(sub))
+        .unwrap_or(host);
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, value)| key == name && !value.is_empty())
+            .map(|(_, value)| value.into_owned())
+    };
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+
+    let video = match (host, segments.next()) {
+        ("youtu.be", Some(id)) => Some(id.to_string()),
+        ("youtube.com", Some("watch")) => param("v"),
+        ("youtube.com", Some("shorts" | "embed" | "live")) => segments.next().map(String::from),
+        ("youtube.com", Some("playlist")) => None,
+        _ => return None,
+    };
+    let link = YoutubeLink {
+        video,
+        playlist: param("list"),
+    };
+    if link.video.is_none() && link.playlist.is_none() {
+        return None;
+    }
+    Some(link)
+}
+
+pub fn video_url(id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={}", id)
+}
+
+pub fn playlist_url(id: &str) -> String {
+    format!("https://www.youtube.com/playlist?list={}", id)
+}
+
+/// Lists the ids of the first `max_items` videos of a playlist, reporting
+/// each page fetched from the API.
+pub async fn fetch_playlist(
+    backend: PlaylistBackend,
+    playlist_id: &str,
+    max_items: usize,
+    progress: &dyn Progress,
+) -> anyhow::Result<Vec<String>> {
+    match backend {
+        PlaylistBackend::Api => api_playlist(playlist_id, max_items, progress).await,
+        PlaylistBackend::Ytdl => ytdl_playlist(playlist_id, max_items).await,
+    }
+}
+
+async fn api_playlist(
+    playlist_id: &str,
+    max_items: usize,
+    progress: &dyn Progress,
+) -> anyhow::Result<Vec<String>> {
+    let key = match env::var("GOOGLE_TOKEN") {
+        Ok(key) => key,
+        Err(_) => bail!("Importing playlists through the YouTube API needs a GOOGLE_TOKEN"),
+    };
+    let client = reqwest::Client::builder()
+        .user_agent("User agent: timothee.leberre@gmail.com")
+        .build()?;
+    let mut videos = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut url = format!("https://www.googleapis.com/youtube/v3/playlistItems?part=snippet&maxResults=50&playlistId={}&key={}", playlist_id, key);
+        if let Some(token) = &page_token {
+            url.push_str(&format!("&pageToken={}", token));
+        }
+        let resp = client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()
+            .context("Error fetching the playlist")?
+            .json::<Playlist>()
+            .await?;
+        videos.extend(
+            resp.items
+                .into_iter()
+                .map(|item| item.snippet.resourceId.videoId),
+        );
+        let total = resp.pageInfo.totalResults.min(max_items);
+        progress
+            .report(format!(
+                "Importing playlist: {}/{} songs",
+                videos.len().min(total),
+                total
+            ))
+            .await;
+        page_token = resp.nextPageToken;
+        if page_token.is_none() || videos.len() >= max_items {
+            break;
+        }
+    }
+    videos.truncate(max_items);
+    Ok(videos)
+}
+
+async fn ytdl_playlist(playlist_id: &str, max_items: usize) -> anyhow::Result<Vec<String>> {
+    let entries = flat_entries(&playlist_url(playlist_id), max_items).await?;
+    Ok(entries.into_iter().map(|entry| entry.id).collect())
+}
+
+/// Lists the first `max_items` entries of a playlist or search with youtube-dl,
+/// without fetching each of the videos.
+async fn flat_entries(url: &str, max_items: usize) -> anyhow::Result<Vec<FlatEntry>> {
+    let output = Command::new(YOUTUBE_DL_COMMAND)
+        .arg("--flat-playlist")
+        .arg("--dump-single-json")
+        .arg("--playlist-end")
+        .arg(max_items.to_string())
+        .arg(url)
+        .output()
+        .await
+        .with_context(|| format!("Error running {}", YOUTUBE_DL_COMMAND))?;
+    if !output.status.success() {
+        bail!(
+            "Error running {}: {}",
+            YOUTUBE_DL_COMMAND,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let playlist = serde_json::from_slice::<FlatPlaylist>(&output.stdout)?;
+    Ok(playlist.entries.into_iter().take(max_items).collect())
+}
+
+/// A video found by a search.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub id: String,
+    pub title: String,
+    pub channel: String,
+    pub duration: Option<Duration>,
+}
+
+/// Lists the first `count` videos found on YouTube for `terms`.
+pub async fn search(terms: &str, count: usize) -> anyhow::Result<Vec<SearchResult>> {
+    let entries = flat_entries(&format!("ytsearch{}:{}", count, terms), count).await?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| SearchResult {
+            id: entry.id,
+            title: entry.title,
+            channel: entry.channel.or(entry.uploader).unwrap_or_default(),
+            duration: entry.duration.map(Duration::from_secs_f64),
+        })
+        .collect())
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct FlatPlaylist {
+    entries: Vec<FlatEntry>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct FlatEntry {
+    id: String,
+    title: String,
+    channel: Option<String>,
+    uploader: Option<String>,
+    duration: Option<f64>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(non_snake_case)]
+struct Playlist {
+    nextPageToken: Option<String>,
+    pageInfo: PageInfo,
+    items: Vec<Item>,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(non_snake_case)]
+struct PageInfo {
+    totalResults: usize,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(non_snake_case)]
+struct Item {
+    snippet: Snippet,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(non_snake_case)]
+struct Snippet {
+    resourceId: RessourceId,
+}
+
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[allow(non_snake_case)]
+struct RessourceId {
+    videoId: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(video: Option<&str>, playlist: Option<&str>) -> Option<YoutubeLink> {
+        Some(YoutubeLink {
+            video: video.map(String::from),
+            playlist: playlist.map(String::from),
+        })
+    }
+
+    #[test]
+    fn parses_videos() {
+        let video = link(Some("dQw4w9WgXcQ"), None);
+        assert_eq!(
+            parse_link("https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+            video
+        );
+        assert_eq!(parse_link("https://youtu.be/dQw4w9WgXcQ"), video);
+        assert_eq!(
+            parse_link("https://music.youtube.com/watch?v=dQw4w9WgXcQ"),
+            video
+        );
+        assert_eq!(
+            parse_link("https://m.youtube.com/watch?v=dQw4w9WgXcQ&t=42"),
+            video
+        );
+        assert_eq!(
+            parse_link("https://www.youtube.com/shorts/dQw4w9WgXcQ"),
+            video
+        );
+    }
+
+    #[test]
+    fn parses_playlists() {
+        assert_eq!(
+            parse_link("https://www.youtube.com/playlist?list=PL123"),
+            link(None, Some("PL123"))
+        );
+        assert_eq!(
+            parse_link("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123&index=4"),
+            link(Some("dQw4w9WgXcQ"), Some("PL123"))
+        );
+        assert_eq!(
+            parse_link("https://youtu.be/dQw4w9WgXcQ?list=PL123"),
+            link(Some("dQw4w9WgXcQ"), Some("PL123"))
+        );
+    }
+
+    #[test]
+    fn rejects_other_links() {
+        assert_eq!(parse_link("https://example.com/watch?v=dQw4w9WgXcQ"), None);
+        assert_eq!(parse_link("https://www.youtube.com/watch"), None);
+        assert_eq!(parse_link("https://www.youtube.com/playlist"), None);
+        assert_eq!(parse_link("never gonna give you up"), None);
+    }
+
+    #[test]
+    fn canonical_urls() {
+        let link = parse_link("https://youtu.be/dQw4w9WgXcQ?list=PL123&index=4&si=abc").unwrap();
+        assert_eq!(
+            link.url(),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123"
+        );
+        assert_eq!(
+            link.video_only().unwrap().url(),
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ"
+        );
+    }
+}